@@ -0,0 +1,451 @@
+//! Settings from `~/.config/todo/config.toml`, overridden by `.todo/config.toml`
+//! of the project directory. Load them once with [`Config::load`] and install
+//! with [`init`], everything else reads them with [`get`].
+
+use crate::tasks::TASKS_CSV;
+use crate::{Error, Result, TODO_DIR};
+use chrono::NaiveDate;
+use chrono::format::{Item, StrftimeItems};
+use homedir::my_home;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+use termimad::crossterm::style::Color;
+use toml::{Table, Value};
+
+pub const CONFIG_TOML: &str = "config.toml";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Makes `config` the one returned by [`get`], only the first call counts
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        log::debug!("config is already initialized");
+    }
+}
+
+/// The installed config, the defaults if [`init`] wasn't called
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Second keyboard layout: a search typed in the English layout by mistake is
+/// converted to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    En,
+    Ru,
+    Uk,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Colors when stdout is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Search {
+    /// A word this similar to a title word is a match on its own
+    pub similar_word: f64,
+    /// Average similarity of the needle words for a match
+    pub similar_average: f64,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            similar_word: 0.999,
+            similar_average: 0.85,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Tasks file, relative to the config file directory. `TASKS_FILE` wins.
+    pub tasks_file: String,
+    /// Arguments used when `todo` is run without a command, e.g. `list #work`
+    pub default_command: String,
+    /// Editor for comments, `EDITOR` and `VISUAL` come next
+    pub editor: String,
+    /// Editors tried when no editor is set, the first installed is used
+    pub editors: Vec<String>,
+    /// Ask before removing tasks and before changing the `--query` matches
+    pub confirm: bool,
+    /// `strftime` format of due and scheduled dates in the text output
+    pub date_format: String,
+    pub locale: Locale,
+    pub color: ColorMode,
+    /// Color of overdue tasks: a name like `red` or `dark_yellow`
+    pub overdue_color: String,
+    pub search: Search,
+    /// `tasks_file` resolved against the config file that sets it
+    #[serde(skip)]
+    configured: Option<Configured>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tasks_file: String::new(),
+            default_command: String::new(),
+            editor: String::new(),
+            editors: ["nvim", "vim", "vi", "nano"].map(String::from).into(),
+            confirm: true,
+            date_format: "%Y-%m-%d".into(),
+            locale: Locale::Ru,
+            color: ColorMode::Auto,
+            overdue_color: "red".into(),
+            search: Search::default(),
+            configured: None,
+        }
+    }
+}
+
+/// The tasks file set by `tasks_file`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configured {
+    pub path: PathBuf,
+    /// The config file with the setting
+    pub config: PathBuf,
+    /// Set by the project directory config
+    pub local: bool,
+}
+
+/// Paths of the config files, whether they exist or not
+#[derive(Debug, Clone)]
+pub struct ConfigPaths {
+    pub global: PathBuf,
+    /// `.todo/config.toml` of the project directory
+    pub local: Option<PathBuf>,
+}
+
+impl ConfigPaths {
+    pub fn find() -> Self {
+        let home = home_dir();
+        let global = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(|| home.join(".config"), PathBuf::from)
+            .join("todo")
+            .join(CONFIG_TOML);
+        let local = env::current_dir()
+            .ok()
+            .and_then(|cwd| project_dir(&cwd, &home, CONFIG_TOML).map(local_path));
+        Self { global, local }
+    }
+
+    /// The file `config set --local` changes: the project directory config,
+    /// a new one next to the project tasks file or in the current directory
+    pub fn local_or_new(&self) -> io::Result<PathBuf> {
+        match &self.local {
+            Some(path) => Ok(path.clone()),
+            None => {
+                let cwd = env::current_dir()?;
+                let dir = project_dir(&cwd, &home_dir(), TASKS_CSV).unwrap_or(&cwd);
+                Ok(local_path(dir))
+            }
+        }
+    }
+}
+
+fn local_path(dir: &Path) -> PathBuf {
+    dir.join(TODO_DIR).join(CONFIG_TOML)
+}
+
+pub(crate) fn home_dir() -> PathBuf {
+    my_home()
+        .transpose()
+        .unwrap()
+        .expect("cannot determine home directory")
+}
+
+/// The closest directory with `.todo/<file>` in it: `cwd` or a parent below
+/// `home`. Like git searches for `.git`.
+pub(crate) fn project_dir<'a>(cwd: &'a Path, home: &Path, file: &str) -> Option<&'a Path> {
+    cwd.ancestors()
+        .take_while(|dir| *dir != home)
+        .find(|dir| dir.join(TODO_DIR).join(file).is_file())
+}
+
+impl Config {
+    /// Reads the global config and the project directory one over it
+    pub fn load() -> Result<Self> {
+        let paths = ConfigPaths::find();
+        let mut layers = vec![(paths.global, false)];
+        layers.extend(paths.local.map(|path| (path, true)));
+        Self::load_layers(&layers)
+    }
+
+    /// Reads config files `(path, local)`, the later ones win. Missing files
+    /// are skipped.
+    fn load_layers(layers: &[(PathBuf, bool)]) -> Result<Self> {
+        let mut merged = Table::new();
+        let mut configured = None;
+        for (path, local) in layers {
+            let Some(table) = read_table(path)? else {
+                continue;
+            };
+            for key in flatten(&table).into_iter().map(|(key, _)| key) {
+                if default_value(&key).is_none() {
+                    log::error!("{path:?}: unknown setting {key}, ignored");
+                }
+            }
+            if let Some(tasks_file) = table.get("tasks_file").and_then(Value::as_str)
+                && !tasks_file.trim().is_empty()
+            {
+                configured = Some(Configured {
+                    path: resolve(path, tasks_file.trim()),
+                    config: path.clone(),
+                    local: *local,
+                });
+            }
+            merge(&mut merged, table);
+            let config = Self::from_table(merged.clone()).map_err(|message| Error::Config {
+                path: path.clone(),
+                message,
+            })?;
+            log::debug!("loaded {path:?}: {config:?}");
+        }
+        let mut config = Self::from_table(merged).unwrap_or_default();
+        config.configured = configured;
+        Ok(config)
+    }
+
+    fn from_table(table: Table) -> Result<Self, String> {
+        let config: Self = Value::Table(table)
+            .try_into()
+            .map_err(|err: toml::de::Error| err.message().to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (key, value) in [
+            ("search.similar_word", self.search.similar_word),
+            ("search.similar_average", self.search.similar_average),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{key} must be from 0 to 1, got {value}"));
+            }
+        }
+        if StrftimeItems::new(&self.date_format).any(|item| item == Item::Error) {
+            return Err(format!("invalid date_format {:?}", self.date_format));
+        }
+        self.overdue_color()?;
+        Ok(())
+    }
+
+    /// `tasks_file` as a path with the config file that sets it
+    pub fn configured(&self) -> Option<&Configured> {
+        self.configured.as_ref()
+    }
+
+    pub fn overdue_color(&self) -> Result<Color, String> {
+        Color::try_from(self.overdue_color.as_str())
+            .map_err(|()| format!("unknown overdue_color {:?}", self.overdue_color))
+    }
+
+    /// A date in `date_format`
+    pub fn date(&self, date: NaiveDate) -> impl Display + '_ {
+        date.format(&self.date_format)
+    }
+
+    /// Every setting as `(key, value)`, nested keys are dotted:
+    /// `search.similar_word`
+    pub fn entries(&self) -> Vec<(String, Value)> {
+        flatten(&to_table(self))
+    }
+
+    pub fn value(&self, key: &str) -> Option<Value> {
+        self.entries()
+            .into_iter()
+            .find_map(|(k, value)| (k == key).then_some(value))
+    }
+}
+
+/// Changes a setting in the config file at `path`, `None` removes it so the
+/// default is used. The whole file is checked before it's written. Returns
+/// the stored value.
+pub fn set(path: &Path, key: &str, value: Option<&str>) -> Result<Option<Value>> {
+    let error = |message| Error::Config {
+        path: path.into(),
+        message,
+    };
+    let default = default_value(key).ok_or_else(|| error(format!("unknown setting {key}")))?;
+    let mut table = read_table(path)?.unwrap_or_default();
+    let (parents, name) = match key.rsplit_once('.') {
+        Some((parents, name)) => (parents.split('.').collect(), name),
+        None => (vec![], key),
+    };
+    let mut section = &mut table;
+    for parent in parents {
+        let entry = section
+            .entry(parent)
+            .or_insert_with(|| Value::Table(Table::new()));
+        section = match entry {
+            Value::Table(section) => section,
+            _ => return Err(error(format!("{parent} is not a table"))),
+        };
+    }
+    let value = value
+        .map(|value| parse_value(value, &default).map_err(error))
+        .transpose()?;
+    match &value {
+        Some(value) => section.insert(name.into(), value.clone()),
+        None => section.remove(name),
+    };
+    let mut merged = to_table(&Config::default());
+    merge(&mut merged, table.clone());
+    Config::from_table(merged).map_err(error)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = toml::to_string_pretty(&table).map_err(|err| error(err.to_string()))?;
+    fs::write(path, content)?;
+    Ok(value)
+}
+
+/// Strings are taken as is, other values are TOML: `0.9`, `false`, `["hx"]`
+fn parse_value(raw: &str, default: &Value) -> Result<Value, String> {
+    if default.is_str() {
+        return Ok(Value::String(raw.into()));
+    }
+    let mut parsed: Table = format!("value = {raw}")
+        .parse()
+        .map_err(|_| format!("{raw:?} is not a valid {}", default.type_str()))?;
+    let value = parsed.remove("value").unwrap_or(Value::Boolean(false));
+    // Integers are fine for floats: `similar_average = 1`
+    match (value, default) {
+        (Value::Integer(n), Value::Float(_)) => Ok(Value::Float(n as f64)),
+        (value, default) if value.same_type(default) => Ok(value),
+        (value, default) => Err(format!(
+            "expected a {}, got a {}",
+            default.type_str(),
+            value.type_str()
+        )),
+    }
+}
+
+fn default_value(key: &str) -> Option<Value> {
+    Config::default().value(key)
+}
+
+fn read_table(path: &Path) -> Result<Option<Table>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let table = content
+        .parse()
+        .map_err(|err: toml::de::Error| Error::Config {
+            path: path.into(),
+            message: err.message().to_string(),
+        })?;
+    Ok(Some(table))
+}
+
+fn to_table(config: &Config) -> Table {
+    Table::try_from(config).expect("config is a table")
+}
+
+fn flatten(table: &Table) -> Vec<(String, Value)> {
+    let mut entries = vec![];
+    for (key, value) in table {
+        match value {
+            Value::Table(section) => entries.extend(
+                flatten(section)
+                    .into_iter()
+                    .map(|(name, value)| (format!("{key}.{name}"), value)),
+            ),
+            value => entries.push((key.clone(), value.clone())),
+        }
+    }
+    entries
+}
+
+/// Puts `from` over `into`, sections are merged key by key
+fn merge(into: &mut Table, from: Table) {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(into)), Value::Table(from)) => merge(into, from),
+            (_, value) => {
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `~/...` is in the home directory, a relative path is next to the config
+fn resolve(config: &Path, path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        return home_dir().join(rest);
+    }
+    config.parent().unwrap_or(Path::new("")).join(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let global = dir.path().join("global.toml");
+        let local = dir.path().join("project/.todo/config.toml");
+        set(&global, "search.similar_average", Some("0.9")).unwrap();
+        set(&global, "tasks_file", Some("tasks.jsonl")).unwrap();
+        set(&global, "confirm", Some("false")).unwrap();
+        set(&local, "search.similar_average", Some("1")).unwrap();
+        set(&local, "editors", Some(r#"["hx"]"#)).unwrap();
+
+        let config = Config::load_layers(&[(global.clone(), false), (local, true)]).unwrap();
+        assert_eq!(config.search.similar_average, 1.0);
+        assert_eq!(config.search.similar_word, 0.999);
+        assert!(!config.confirm);
+        assert_eq!(config.editors, ["hx"]);
+        assert_eq!(
+            config.configured(),
+            Some(&Configured {
+                path: dir.path().join("tasks.jsonl"),
+                config: global.clone(),
+                local: false,
+            })
+        );
+        assert_eq!(
+            config.value("search.similar_average"),
+            Some(Value::Float(1.0))
+        );
+
+        set(&global, "confirm", None).unwrap();
+        let config = Config::load_layers(&[(global.clone(), false)]).unwrap();
+        assert!(config.confirm);
+    }
+
+    #[test]
+    fn test_set_checks_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        for (key, value) in [
+            ("unknown", "1"),
+            ("confirm", "maybe"),
+            ("search.similar_word", "2"),
+            ("date_format", "%Q"),
+            ("locale", "fr"),
+            ("overdue_color", "reddish"),
+        ] {
+            let err = set(&path, key, Some(value)).unwrap_err();
+            assert!(matches!(err, Error::Config { .. }), "{key} = {value}");
+        }
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::Task;
+use crate::storage::Rejected;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum Issue {
+    /// A stored task that cannot be parsed at all
+    Unparseable(Rejected),
+    DuplicateId {
+        id: usize,
+        count: usize,
+    },
+    UpdatedBeforeCreated {
+        id: usize,
+    },
+    FromFuture {
+        id: usize,
+    },
+}
+
+impl Issue {
+    /// Stable name of the problem for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Issue::Unparseable(_) => "unparseable",
+            Issue::DuplicateId { .. } => "duplicate_id",
+            Issue::UpdatedBeforeCreated { .. } => "updated_before_created",
+            Issue::FromFuture { .. } => "from_future",
+        }
+    }
+
+    pub fn id(&self) -> Option<usize> {
+        match self {
+            Issue::Unparseable(rejected) => rejected.id,
+            Issue::DuplicateId { id, .. }
+            | Issue::UpdatedBeforeCreated { id }
+            | Issue::FromFuture { id } => Some(*id),
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Unparseable(rejected) => {
+                let line = rejected.line;
+                match rejected.field {
+                    Some("id") => write!(f, "line {line}: invalid ID")?,
+                    Some("status") => write!(f, "line {line}: unknown status")?,
+                    Some(field @ ("created_at" | "updated_at")) => {
+                        write!(f, "line {line}: invalid timestamp in {field}")?
+                    }
+                    Some(field @ ("history" | "blocked_by" | "time")) => {
+                        write!(f, "line {line}: invalid JSON list in {field}")?
+                    }
+                    _ => write!(f, "line {line}: cannot parse the task: {}", rejected.error)?,
+                }
+                write!(f, "\n    {}", rejected.raw)
+            }
+            Issue::DuplicateId { id, count } => write!(f, "ID {id} is used by {count} tasks"),
+            Issue::UpdatedBeforeCreated { id } => {
+                write!(f, "task {id} was updated before it was created")
+            }
+            Issue::FromFuture { id } => write!(f, "task {id} has timestamps in the future"),
+        }
+    }
+}
+
+pub fn diagnose(tasks: &[Task], rejected: &[Rejected], now: DateTime<Utc>) -> Vec<Issue> {
+    let mut issues: Vec<_> = rejected.iter().cloned().map(Issue::Unparseable).collect();
+
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for task in tasks {
+        *counts.entry(task.id).or_default() += 1;
+    }
+    let mut duplicates: Vec<_> = counts.into_iter().filter(|(_, n)| *n > 1).collect();
+    duplicates.sort_unstable();
+    issues.extend(
+        duplicates
+            .into_iter()
+            .map(|(id, count)| Issue::DuplicateId { id, count }),
+    );
+
+    for task in tasks {
+        if task.updated_at < task.created_at {
+            issues.push(Issue::UpdatedBeforeCreated { id: task.id });
+        }
+        if task.created_at > now || task.updated_at > now {
+            issues.push(Issue::FromFuture { id: task.id });
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::task;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_diagnose() {
+        let mut tasks = vec![task(1, "buy milk"), task(2, "buy beer"), task(2, "bake")];
+        let now = Utc::now();
+        tasks[0].updated_at = tasks[0].created_at - TimeDelta::days(1);
+        tasks[1].created_at = now + TimeDelta::days(1);
+        tasks[1].updated_at = now + TimeDelta::days(1);
+
+        let issues: Vec<_> = diagnose(&tasks, &[], now)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "ID 2 is used by 2 tasks",
+                "task 1 was updated before it was created",
+                "task 2 has timestamps in the future",
+            ]
+        );
+    }
+}
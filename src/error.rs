@@ -0,0 +1,69 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{error, fmt, io};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the tasks file failed
+    Io(io::Error),
+    /// Another process held the tasks file lock longer than the timeout
+    Locked { path: PathBuf, timeout: Duration },
+    /// Some stored tasks cannot be parsed, saving would lose them
+    Rejected { path: PathBuf, count: usize },
+    /// A file that must not be overwritten already exists
+    AlreadyExists(PathBuf),
+    /// Unknown storage format name
+    UnknownFormat(String),
+    /// A config file cannot be parsed or has a wrong setting
+    Config { path: PathBuf, message: String },
+    /// Blocking a task would make it wait for itself, the IDs of the cycle
+    Cycle(Vec<usize>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Locked { path, timeout } => write!(
+                f,
+                "{path:?} is locked by another todo process, gave up after {:.1}s \
+                 (set TODO_LOCK_TIMEOUT to wait longer)",
+                timeout.as_secs_f64()
+            ),
+            Error::Rejected { path, count } => write!(
+                f,
+                "{count} tasks of {path:?} cannot be parsed, fix them before making changes \
+                 or run `todo doctor --fix` to move them to {:?}",
+                crate::storage::rejected_path(path),
+            ),
+            Error::AlreadyExists(path) => write!(f, "{path:?} already exists"),
+            Error::UnknownFormat(name) => write!(
+                f,
+                "unknown format {name:?}, expected csv, jsonl, toml or sqlite"
+            ),
+            Error::Config { path, message } => write!(f, "{path:?}: {message}"),
+            Error::Cycle(ids) => {
+                let ids: Vec<_> = ids.iter().map(ToString::to_string).collect();
+                write!(f, "tasks would wait for each other: {}", ids.join(" -> "))
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
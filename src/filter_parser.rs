@@ -1,15 +1,16 @@
 #[cfg(not(test))]
 use chrono::Local;
 
-use chrono::{Datelike, NaiveDate};
+use crate::{Priority, Recurrence, Task};
+use chrono::{Datelike, NaiveDate, Weekday};
 use nom::IResult;
 use nom::Parser;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while1};
 use nom::character::complete::{digit1, multispace0, multispace1, space0, space1};
-use nom::combinator::{map, map_res, opt};
-use nom::multi::many_m_n;
-use nom::sequence::{pair, preceded};
+use nom::combinator::{map, map_opt, map_res, opt, recognize};
+use nom::multi::{many_m_n, separated_list1};
+use nom::sequence::{pair, preceded, terminated};
 use std::ops::RangeInclusive;
 use std::str::FromStr;
 
@@ -79,7 +80,7 @@ fn tdby(input: &str) -> IResult<&str, NaiveDate> {
     .parse(input)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TimeUnit {
     Days,
     Weeks,
@@ -93,18 +94,50 @@ impl FromStr for TimeUnit {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "days" | "day" | "день" | "дней" | "дня" => Ok(Self::Days),
-            "weeks" | "week" | "неделя" | "недели" | "недель" | "неделю" => {
+            "weeks" | "week" | "неделя" | "недели" | "недель" | "неделю" | "неделе" => {
                 Ok(Self::Weeks)
             }
-            "months" | "month" | "месяцев" | "месяца" | "месяц" => {
+            "months" | "month" | "месяцев" | "месяца" | "месяц" | "месяце" => {
                 Ok(Self::Months)
             }
-            "years" | "year" | "года" | "год" | "лет" => Ok(Self::Years),
+            "years" | "year" | "года" | "год" | "лет" | "году" => Ok(Self::Years),
             _ => Err(()),
         }
     }
 }
 
+impl TimeUnit {
+    /// `date` moved `amount` units forward, the end of month is kept for
+    /// months and years: Jan 31 + 1 month is Feb 28. `None` if the date is out
+    /// of range.
+    pub fn add(self, date: NaiveDate, amount: u32) -> Option<NaiveDate> {
+        match self {
+            TimeUnit::Days => date.checked_add_signed(chrono::TimeDelta::try_days(amount.into())?),
+            TimeUnit::Weeks => {
+                date.checked_add_signed(chrono::TimeDelta::try_weeks(amount.into())?)
+            }
+            TimeUnit::Months => date.checked_add_months(chrono::Months::new(amount)),
+            TimeUnit::Years => {
+                date.checked_add_months(chrono::Months::new(amount.checked_mul(12)?))
+            }
+        }
+    }
+
+    /// `date` moved `amount` units back, `None` if the date is out of range
+    pub fn sub(self, date: NaiveDate, amount: u32) -> Option<NaiveDate> {
+        match self {
+            TimeUnit::Days => date.checked_sub_signed(chrono::TimeDelta::try_days(amount.into())?),
+            TimeUnit::Weeks => {
+                date.checked_sub_signed(chrono::TimeDelta::try_weeks(amount.into())?)
+            }
+            TimeUnit::Months => date.checked_sub_months(chrono::Months::new(amount)),
+            TimeUnit::Years => {
+                date.checked_sub_months(chrono::Months::new(amount.checked_mul(12)?))
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct TimeOffset {
     pub amount: u32,
@@ -112,23 +145,17 @@ pub struct TimeOffset {
 }
 
 impl TimeOffset {
-    fn into_date(self) -> NaiveDate {
-        let today_ = today();
-        match self.unit {
-            TimeUnit::Days => today_ - chrono::TimeDelta::days(self.amount as i64),
-            TimeUnit::Weeks => today_ - chrono::TimeDelta::weeks(self.amount as i64),
-            TimeUnit::Months => today_
-                .checked_sub_months(chrono::Months::new(self.amount))
-                .unwrap(),
-            TimeUnit::Years => today_
-                .checked_sub_months(chrono::Months::new(self.amount * 12))
-                .unwrap(),
-        }
+    fn into_date(self) -> Option<NaiveDate> {
+        self.unit.sub(today(), self.amount)
+    }
+
+    fn into_future_date(self) -> Option<NaiveDate> {
+        self.unit.add(today(), self.amount)
     }
 }
 
 fn number(input: &str) -> IResult<&str, u32> {
-    map(digit1, |s: &str| u32::from_str(s).unwrap()).parse(input)
+    map_res(digit1, u32::from_str).parse(input)
 }
 
 fn time_unit(input: &str) -> IResult<&str, TimeUnit> {
@@ -151,25 +178,287 @@ pub fn parse_offset(input: &str) -> IResult<&str, NaiveDate> {
         unit,
     });
 
-    map(alt((with_number, without_number)), TimeOffset::into_date).parse(input)
+    map_opt(alt((with_number, without_number)), TimeOffset::into_date).parse(input)
+}
+
+fn tomorrow(input: &str) -> IResult<&str, NaiveDate> {
+    alt((
+        map(
+            alt((tag("day after tomorrow"), tag("послезавтра"))),
+            |_| today().succ_opt().unwrap().succ_opt().unwrap(),
+        ),
+        map(alt((tag("tomorrow"), tag("завтра"))), |_| {
+            today().succ_opt().unwrap()
+        }),
+    ))
+    .parse(input)
 }
 
+/// `in 3 days`, `через 2 недели`, `in a week`
+fn parse_future_offset(input: &str) -> IResult<&str, NaiveDate> {
+    map_opt(
+        (
+            alt((tag("in"), tag("через"))),
+            space1,
+            opt(terminated(alt((number, map(tag("a"), |_| 1))), space1)),
+            time_unit,
+        ),
+        |(_, _, amount, unit)| {
+            let amount = amount.unwrap_or(1);
+            TimeOffset { amount, unit }.into_future_date()
+        },
+    )
+    .parse(input)
+}
+
+pub(crate) fn parse_weekday(s: &str) -> Result<Weekday, ()> {
+    match s {
+        "monday" | "mon" | "понедельник" => Ok(Weekday::Mon),
+        "tuesday" | "tue" | "вторник" => Ok(Weekday::Tue),
+        "wednesday" | "wed" | "среда" | "среду" => Ok(Weekday::Wed),
+        "thursday" | "thu" | "четверг" => Ok(Weekday::Thu),
+        "friday" | "fri" | "пятница" | "пятницу" => Ok(Weekday::Fri),
+        "saturday" | "sat" | "суббота" | "субботу" => Ok(Weekday::Sat),
+        "sunday" | "sun" | "воскресенье" => Ok(Weekday::Sun),
+        _ => Err(()),
+    }
+}
+
+fn weekday(input: &str) -> IResult<&str, Weekday> {
+    preceded(
+        opt(pair(alt((tag("во"), tag("в"))), space1)),
+        map_res(alpha1_utf8, parse_weekday),
+    )
+    .parse(input)
+}
+
+/// The nearest `weekday` after today, or today itself if `include_today`
+fn upcoming(weekday: Weekday, include_today: bool) -> NaiveDate {
+    let today_ = today();
+    let days = (7 + weekday.num_days_from_monday() - today_.weekday().num_days_from_monday()) % 7;
+    let days = if days == 0 && !include_today { 7 } else { days };
+    today_ + chrono::TimeDelta::days(days.into())
+}
+
+/// `next friday` is the nearest friday after today, `next week` is in a week
+fn parse_next(input: &str) -> IResult<&str, NaiveDate> {
+    let next = preceded(
+        opt(pair(alt((tag("во"), tag("в"), tag("на"))), space1)),
+        alt((
+            tag("next"),
+            tag("следующий"),
+            tag("следующую"),
+            tag("следующее"),
+            tag("следующей"),
+            tag("следующая"),
+        )),
+    );
+    preceded(
+        pair(next, space1),
+        alt((
+            map(weekday, |weekday| upcoming(weekday, false)),
+            map_opt(time_unit, |unit| {
+                TimeOffset { amount: 1, unit }.into_future_date()
+            }),
+        )),
+    )
+    .parse(input)
+}
+
+/// A past or a future date: `yesterday`, `2025-05-04`, `02.03`, `3 weeks ago`,
+/// `tomorrow`, `in 3 days`, `friday`, `next monday`
 fn parse_date(input: &str) -> IResult<&str, NaiveDate> {
     alt((
         parse_today,
         yesterday,
         tdby,
+        tomorrow,
         cis_date,
         iso_date,
         parse_offset,
+        parse_future_offset,
+        parse_next,
+        map(weekday, |weekday| upcoming(weekday, true)),
+    ))
+    .parse(input)
+}
+
+fn every(input: &str) -> IResult<&str, &str> {
+    alt((
+        tag("every"),
+        tag("каждый"),
+        tag("каждую"),
+        tag("каждое"),
+        tag("каждые"),
+        tag("каждая"),
+    ))
+    .parse(input)
+}
+
+/// `2nd of month`, `2-е число`
+fn day_of_month(input: &str) -> IResult<&str, u32> {
+    let suffix = alt((
+        tag("st"),
+        tag("nd"),
+        tag("rd"),
+        tag("th"),
+        tag("-е"),
+        tag("-го"),
+    ));
+    let of_month = alt((
+        tag("day of the month"),
+        tag("day of month"),
+        tag("of the month"),
+        tag("of month"),
+        tag("числа"),
+        tag("число"),
+    ));
+    map_res(
+        (number, opt(suffix), space1, of_month),
+        |(day, _, _, _)| match day {
+            1..=31 => Ok(day),
+            _ => Err(()),
+        },
+    )
+    .parse(input)
+}
+
+fn interval(input: &str) -> IResult<&str, Recurrence> {
+    map_res(
+        pair(opt(terminated(number, space1)), time_unit),
+        |(n, unit)| match n.unwrap_or(1) {
+            0 => Err(()),
+            // Too long to ever come
+            n if unit.add(today(), n).is_none() => Err(()),
+            n => Ok(Recurrence::Every(n, unit)),
+        },
+    )
+    .parse(input)
+}
+
+fn recurrence_shortcut(input: &str) -> IResult<&str, Recurrence> {
+    map_res(alpha1_utf8, |word: &str| {
+        let unit = match word {
+            "daily" | "ежедневно" => TimeUnit::Days,
+            "weekly" | "еженедельно" => TimeUnit::Weeks,
+            "monthly" | "ежемесячно" => TimeUnit::Months,
+            "yearly" | "annually" | "ежегодно" => TimeUnit::Years,
+            _ => return Err(()),
+        };
+        Ok(Recurrence::Every(1, unit))
+    })
+    .parse(input)
+}
+
+/// `every week`, `every 2 days`, `every friday`, `every 2nd of month`,
+/// `каждый понедельник`, `weekly`
+pub fn recurrence(input: &str) -> IResult<&str, Recurrence> {
+    alt((
+        recurrence_shortcut,
+        preceded(
+            pair(every, space1),
+            alt((
+                map(day_of_month, Recurrence::DayOfMonth),
+                map(weekday, Recurrence::Weekday),
+                interval,
+            )),
+        ),
     ))
     .parse(input)
 }
 
+/// Splits what `parser` fully matches off the end of `input`, the longest
+/// match wins
+fn split_suffix<T>(input: &str, parser: impl Fn(&str) -> IResult<&str, T>) -> Option<(&str, T)> {
+    let input = input.trim();
+    let starts = std::iter::once(0).chain(
+        input
+            .char_indices()
+            .filter(|&(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8()),
+    );
+    for start in starts {
+        let tail = input[start..].to_lowercase();
+        if let Ok(("", value)) = parser(tail.trim()) {
+            return Some((input[..start].trim_end(), value));
+        }
+    }
+    None
+}
+
+/// Splits a date off the end of `input`, the longest date wins:
+/// `12 next week` is task `12` and the date in a week
+pub fn split_date(input: &str) -> Option<(&str, NaiveDate)> {
+    split_suffix(input, parse_date)
+}
+
+/// Splits a recurrence rule off the end of `input`: `12 every friday`
+pub fn split_recurrence(input: &str) -> Option<(&str, Recurrence)> {
+    split_suffix(input, recurrence)
+}
+
+/// Dates, the recurrence rule and the estimate set when a task is created:
+/// `pay rent every month due friday scheduled tomorrow ~15m`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Planned {
+    pub due: Option<NaiveDate>,
+    pub scheduled: Option<NaiveDate>,
+    pub recurrence: Option<Recurrence>,
+    /// Seconds
+    pub estimate: Option<u64>,
+}
+
+/// Splits `due <date>`, `scheduled <date>`, `every <rule>` and `~<time>` off
+/// the end of a new task's title
+pub fn planned(title: &str) -> (Planned, &str) {
+    let mut planned = Planned::default();
+    let mut title = title.trim();
+    loop {
+        if planned.estimate.is_none()
+            && let Some((head, word)) = title.rsplit_once(char::is_whitespace)
+            && let Some(estimate) = word.strip_prefix('~').and_then(parse_duration)
+            && estimate > 0
+        {
+            planned.estimate = Some(estimate);
+            title = head.trim_end();
+            continue;
+        }
+        if planned.recurrence.is_none() {
+            match split_recurrence(title) {
+                Some(("", _)) | None => {}
+                Some((head, recurrence)) => {
+                    planned.recurrence = Some(recurrence);
+                    title = head;
+                    continue;
+                }
+            }
+        }
+        let Some((head, date)) = split_date(title) else {
+            break;
+        };
+        let Some((rest, keyword)) = head.rsplit_once(char::is_whitespace) else {
+            break;
+        };
+        let slot = match keyword.to_lowercase().parse() {
+            Ok(Attr::Due) => &mut planned.due,
+            Ok(Attr::Scheduled) => &mut planned.scheduled,
+            _ => break,
+        };
+        if slot.is_some() {
+            break;
+        }
+        *slot = Some(date);
+        title = rest.trim_end();
+    }
+    (planned, title)
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Attr {
     Updated,
     Created,
+    Due,
+    Scheduled,
 }
 
 impl FromStr for Attr {
@@ -179,6 +468,8 @@ impl FromStr for Attr {
         match s {
             "updated" | "обновлено" => Ok(Self::Updated),
             "created" | "создано" => Ok(Self::Created),
+            "due" | "срок" => Ok(Self::Due),
+            "scheduled" | "запланировано" => Ok(Self::Scheduled),
             _ => Err(()),
         }
     }
@@ -236,7 +527,7 @@ fn last_something_en(input: &str) -> IResult<&str, (Option<NaiveDate>, Option<Na
         (tag("last"), space0, opt(number), space0, time_unit),
         |(_, _, num, _, unit)| {
             let amount = num.unwrap_or(1);
-            let start = TimeOffset { amount, unit }.into_date();
+            let start = TimeOffset { amount, unit }.into_date().ok_or(())?;
             Ok::<_, ()>((Some(start), Some(today())))
         },
     )
@@ -244,7 +535,7 @@ fn last_something_en(input: &str) -> IResult<&str, (Option<NaiveDate>, Option<Na
 }
 
 fn last_something_ru(input: &str) -> IResult<&str, (Option<NaiveDate>, Option<NaiveDate>)> {
-    map(
+    map_opt(
         (
             opt(tag("за")),
             space0,
@@ -264,8 +555,8 @@ fn last_something_ru(input: &str) -> IResult<&str, (Option<NaiveDate>, Option<Na
         ),
         |(_, _, num, _, _, _, unit)| {
             let amount = num.unwrap_or(1);
-            let start = TimeOffset { amount, unit }.into_date();
-            (Some(start), Some(today()))
+            let start = TimeOffset { amount, unit }.into_date()?;
+            Some((Some(start), Some(today())))
         },
     )
     .parse(input)
@@ -275,7 +566,9 @@ fn one_day_range(input: &str) -> IResult<&str, (Option<NaiveDate>, Option<NaiveD
     map(parse_date, |x| (Some(x), Some(x))).parse(input)
 }
 
-fn any_range(input: &str) -> IResult<&str, RangeInclusive<NaiveDate>> {
+/// A date range like `last week`, `from 2025-05-01 to 2025-05-31` or a single
+/// day. A missing boundary is [`NaiveDate::MIN`] or [`NaiveDate::MAX`].
+pub fn any_range(input: &str) -> IResult<&str, RangeInclusive<NaiveDate>> {
     map(
         alt((
             date_range,
@@ -288,13 +581,262 @@ fn any_range(input: &str) -> IResult<&str, RangeInclusive<NaiveDate>> {
     .parse(input)
 }
 
+fn decimal(input: &str) -> IResult<&str, f64> {
+    map_res(
+        recognize(pair(digit1, opt(pair(tag("."), digit1)))),
+        f64::from_str,
+    )
+    .parse(input)
+}
+
+/// Seconds in a unit of a duration, a day is a working day of 8 hours
+fn duration_unit(input: &str) -> IResult<&str, u64> {
+    map_res(alpha1_utf8, |unit: &str| match unit {
+        "d" | "day" | "days" | "д" => Ok(8 * 3600),
+        "h" | "hour" | "hours" | "ч" => Ok(3600),
+        "m" | "min" | "mins" | "minutes" | "м" | "мин" => Ok(60),
+        "s" | "sec" | "с" | "сек" => Ok(1),
+        _ => Err(()),
+    })
+    .parse(input)
+}
+
+/// Time like `1h30m`, `1h 30m`, `45m` or `1.5h`, in seconds
+pub fn duration(input: &str) -> IResult<&str, u64> {
+    map(
+        separated_list1(space0, pair(terminated(decimal, space0), duration_unit)),
+        |parts| {
+            parts
+                .into_iter()
+                .map(|(amount, unit)| (amount * unit as f64).round() as u64)
+                .sum()
+        },
+    )
+    .parse(input)
+}
+
+/// The whole input as a [`duration`]
+pub fn parse_duration(input: &str) -> Option<u64> {
+    match duration(input.trim().to_lowercase().as_str()) {
+        Ok(("", seconds)) => Some(seconds),
+        _ => None,
+    }
+}
+
+fn id_range(input: &str) -> IResult<&str, RangeInclusive<usize>> {
+    map_res(
+        (digit1, opt(preceded((space0, tag("-"), space0), digit1))),
+        |(from, to): (&str, Option<&str>)| {
+            let from = from.parse::<usize>().map_err(|_| ())?;
+            let to = to.map_or(Ok(from), |to| to.parse::<usize>().map_err(|_| ()))?;
+            if to < from {
+                return Err(());
+            }
+            Ok(from..=to)
+        },
+    )
+    .parse(input)
+}
+
+/// Explicit task IDs like `3,5,7-12`. A single number is not a list, it may
+/// be a part of a title as well.
+pub fn id_list(input: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    let input = input.trim();
+    if !input.contains([',', '-']) {
+        return None;
+    }
+    match separated_list1((space0, tag(","), space0), id_range).parse(input) {
+        Ok(("", ranges)) => Some(ranges),
+        _ => None,
+    }
+}
+
 pub fn attr_and_range(input: &str) -> IResult<&str, (Attr, RangeInclusive<NaiveDate>)> {
     (attr, preceded(multispace1, any_range)).parse(input)
 }
 
+/// Comparison operator of a filter
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    /// Whether `left <op> right` holds, e.g. `Cmp::Lt.holds(1, 2)`
+    pub fn holds<T: Ord>(self, left: T, right: T) -> bool {
+        match self {
+            Cmp::Eq => left == right,
+            Cmp::Lt => left < right,
+            Cmp::Le => left <= right,
+            Cmp::Gt => left > right,
+            Cmp::Ge => left >= right,
+        }
+    }
+}
+
+fn cmp(input: &str) -> IResult<&str, Cmp> {
+    alt((
+        map(tag(">="), |_| Cmp::Ge),
+        map(tag("<="), |_| Cmp::Le),
+        map(tag(">"), |_| Cmp::Gt),
+        map(tag("<"), |_| Cmp::Lt),
+        map(tag("="), |_| Cmp::Eq),
+    ))
+    .parse(input)
+}
+
+/// `priority >= b`: `>` means more important. Tasks without a priority are
+/// less important than any level and match `priority none`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PriorityFilter {
+    pub cmp: Cmp,
+    pub level: Option<Priority>,
+}
+
+impl PriorityFilter {
+    pub fn matches(&self, priority: Option<Priority>) -> bool {
+        // Reversed so that the more important is the greater, `None` is the least
+        let importance = |p: Option<Priority>| std::cmp::Reverse((p.is_none(), p));
+        self.cmp.holds(importance(priority), importance(self.level))
+    }
+}
+
+fn priority_level(input: &str) -> IResult<&str, Option<Priority>> {
+    map_res(
+        take_while1(|c: char| c.is_alphanumeric()),
+        |level: &str| match level {
+            "none" | "нет" => Ok(None),
+            level => level.parse().map(Some),
+        },
+    )
+    .parse(input)
+}
+
+fn priority_filter(input: &str) -> IResult<&str, PriorityFilter> {
+    let keyword = alt((tag("priority"), tag("prio"), tag("приоритет")));
+    map(
+        (keyword, space0, opt(cmp), space0, priority_level),
+        |(_, _, cmp, _, level)| PriorityFilter {
+            cmp: cmp.unwrap_or(Cmp::Eq),
+            level,
+        },
+    )
+    .parse(input)
+}
+
+/// A task's time a [`DurationFilter`] is about
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DurationAttr {
+    Estimate,
+    Spent,
+}
+
+/// `estimate > 1h` or `spent >= 30m`. Tasks without an estimate or spent time
+/// don't match, `estimate none` finds them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DurationFilter {
+    pub attr: DurationAttr,
+    pub cmp: Cmp,
+    /// Seconds, `None` for `none`
+    pub seconds: Option<u64>,
+}
+
+impl DurationFilter {
+    pub fn matches(&self, task: &Task) -> bool {
+        let value = match self.attr {
+            DurationAttr::Estimate => task.estimate,
+            DurationAttr::Spent => Some(task.spent()).filter(|&spent| spent > 0),
+        };
+        match (value, self.seconds) {
+            (Some(value), Some(seconds)) => self.cmp.holds(value, seconds),
+            (value, None) => value.is_none(),
+            (None, Some(_)) => false,
+        }
+    }
+}
+
+fn duration_filter(input: &str) -> IResult<&str, DurationFilter> {
+    let attr = alt((
+        map(alt((tag("estimate"), tag("оценка"))), |_| {
+            DurationAttr::Estimate
+        }),
+        map(alt((tag("spent"), tag("потрачено"))), |_| {
+            DurationAttr::Spent
+        }),
+    ));
+    let seconds = alt((
+        map(alt((tag("none"), tag("нет"))), |_| None),
+        map(preceded(opt(tag("~")), duration), Some),
+    ));
+    map(
+        (attr, space0, opt(cmp), space0, seconds),
+        |(attr, _, cmp, _, seconds)| DurationFilter {
+            attr,
+            cmp: cmp.unwrap_or(Cmp::Eq),
+            seconds,
+        },
+    )
+    .parse(input)
+}
+
+/// Filters at the start of a search query
+#[derive(Debug, Default)]
+pub struct Filters {
+    pub dates: Vec<(Attr, RangeInclusive<NaiveDate>)>,
+    pub priority: Option<PriorityFilter>,
+    pub durations: Vec<DurationFilter>,
+}
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        self.dates.is_empty() && self.priority.is_none() && self.durations.is_empty()
+    }
+
+    pub fn matches(&self, task: &Task) -> bool {
+        let dates = self.dates.iter().all(|(attr, range)| {
+            let date = match attr {
+                Attr::Updated => Some(task.updated_at.date_naive()),
+                Attr::Created => Some(task.created_at.date_naive()),
+                Attr::Due => task.due,
+                Attr::Scheduled => task.scheduled,
+            };
+            date.is_some_and(|date| range.contains(&date))
+        });
+        dates
+            && self.priority.is_none_or(|p| p.matches(task.priority))
+            && self.durations.iter().all(|filter| filter.matches(task))
+    }
+}
+
+/// Splits filters like `created last week priority >= b estimate > 1h` off
+/// the query, the rest is the search needle
+pub fn filters(input: &str) -> (Filters, &str) {
+    let mut filters = Filters::default();
+    let mut rest = input.trim();
+    loop {
+        if let Ok((tail, (attr, range))) = attr_and_range(rest) {
+            filters.dates.push((attr, range));
+            rest = tail.trim();
+        } else if let Ok((tail, priority)) = priority_filter(rest) {
+            filters.priority = Some(priority);
+            rest = tail.trim();
+        } else if let Ok((tail, duration)) = duration_filter(rest) {
+            filters.durations.push(duration);
+            rest = tail.trim();
+        } else {
+            return (filters, rest);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::tests::round_trip;
 
     #[test]
     fn test_parse_attr_range() {
@@ -303,6 +845,17 @@ mod tests {
         assert_eq!(attr, Attr::Created);
     }
 
+    #[test]
+    fn test_duration() {
+        assert_eq!(parse_duration("1h30m"), Some(5400));
+        assert_eq!(parse_duration("1h 30m"), Some(5400));
+        assert_eq!(parse_duration("45min"), Some(2700));
+        assert_eq!(parse_duration("1.5h"), Some(5400));
+        assert_eq!(parse_duration("2д"), Some(16 * 3600));
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("fix 1h"), None);
+    }
+
     #[test]
     fn test_any_range() {
         let cases = [
@@ -349,6 +902,179 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_future_dates() {
+        // today() is Sunday, 2025-05-04
+        let cases = [
+            ("tomorrow", "2025-05-05"),
+            ("послезавтра", "2025-05-06"),
+            ("in 3 days", "2025-05-07"),
+            ("in a week", "2025-05-11"),
+            ("через 2 недели", "2025-05-18"),
+            ("friday", "2025-05-09"),
+            ("в пятницу", "2025-05-09"),
+            ("sunday", "2025-05-04"),
+            ("next sunday", "2025-05-11"),
+            ("next monday", "2025-05-05"),
+            ("next week", "2025-05-11"),
+            ("на следующей неделе", "2025-05-11"),
+        ];
+        for (input, expected) in cases {
+            let date = NaiveDate::from_str(expected).unwrap();
+            assert_eq!(parse_date(input), Ok(("", date)), "case '{input}' failed");
+        }
+        // Numbers too large for a date fail to parse instead of panicking
+        for input in [
+            "in 99999999999 days",
+            "in 4000000 years",
+            "in 300000000 weeks",
+        ] {
+            assert!(parse_date(input).is_err(), "case '{input}' failed");
+        }
+        assert!(parse_date("4294967295 years ago").is_err());
+        assert!(any_range("last 4294967295 years").is_err());
+    }
+
+    #[test]
+    fn test_dates_stored() {
+        let mut task = Task::new("pay rent".into(), crate::Status::Todo);
+        task.due = parse_date("friday").ok().map(|(_, date)| date);
+        for (format, loaded) in round_trip(&[task.clone()]) {
+            assert_eq!(loaded[0].due, task.due, "{format:?}");
+            assert_eq!(loaded[0].scheduled, None, "{format:?}");
+        }
+        task.scheduled = parse_date("tomorrow").ok().map(|(_, date)| date);
+        for (format, loaded) in round_trip(&[task.clone()]) {
+            assert_eq!(loaded[0].scheduled, task.scheduled, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_split_date() {
+        let date = |s| NaiveDate::from_str(s).unwrap();
+        assert_eq!(split_date("12 next week"), Some(("12", date("2025-05-11"))));
+        assert_eq!(
+            split_date("check in 3 days"),
+            Some(("check", date("2025-05-07")))
+        );
+        assert_eq!(split_date("milk 12.05"), Some(("milk", date("2025-05-12"))));
+        assert_eq!(split_date("buy milk"), None);
+
+        let (dates, title) = planned("Pay rent due Friday ~15m scheduled tomorrow");
+        assert_eq!(title, "Pay rent");
+        assert_eq!(dates.due, Some(date("2025-05-09")));
+        assert_eq!(dates.scheduled, Some(date("2025-05-05")));
+        assert_eq!(dates.estimate, Some(900));
+        assert_eq!(planned("~2h").1, "~2h");
+        assert_eq!(
+            planned("due diligence"),
+            (Planned::default(), "due diligence")
+        );
+        assert_eq!(
+            planned("call mom today"),
+            (Planned::default(), "call mom today")
+        );
+    }
+
+    #[test]
+    fn test_recurrence() {
+        let cases = [
+            ("every day", Recurrence::Every(1, TimeUnit::Days)),
+            ("every 2 weeks", Recurrence::Every(2, TimeUnit::Weeks)),
+            ("weekly", Recurrence::Every(1, TimeUnit::Weeks)),
+            ("каждые 3 месяца", Recurrence::Every(3, TimeUnit::Months)),
+            ("every friday", Recurrence::Weekday(Weekday::Fri)),
+            ("каждый понедельник", Recurrence::Weekday(Weekday::Mon)),
+            ("каждую среду", Recurrence::Weekday(Weekday::Wed)),
+            ("every 2nd of month", Recurrence::DayOfMonth(2)),
+            ("каждое 15-е число", Recurrence::DayOfMonth(15)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                recurrence(input),
+                Ok(("", expected)),
+                "case '{input}' failed"
+            );
+            let canonical = expected.to_string();
+            assert_eq!(canonical.parse(), Ok(expected), "case '{canonical}' failed");
+        }
+        assert!(recurrence("every 0 days").is_err());
+        assert!(recurrence("every 32nd of month").is_err());
+        assert!(recurrence("every 4294967295 years").is_err());
+        assert!(recurrence("every 99999999999 days").is_err());
+
+        let (dates, title) = planned("invoice every month due 05.05");
+        assert_eq!(title, "invoice");
+        assert_eq!(
+            dates.recurrence,
+            Some(Recurrence::Every(1, TimeUnit::Months))
+        );
+        assert_eq!(dates.due, NaiveDate::from_ymd_opt(2025, 5, 5));
+        assert_eq!(
+            split_recurrence("12 every 2nd of month"),
+            Some(("12", Recurrence::DayOfMonth(2)))
+        );
+        assert_eq!(planned("every day").1, "every day");
+    }
+
+    #[test]
+    fn test_priority_filter() {
+        let (found, needle) = filters("prio >= b created today milk");
+        assert_eq!(needle, "milk");
+        assert_eq!(found.dates.len(), 1);
+        let priority = found.priority.unwrap();
+        assert_eq!(priority.cmp, Cmp::Ge);
+        assert!(priority.matches(Some(Priority::A)));
+        assert!(priority.matches(Some(Priority::B)));
+        assert!(!priority.matches(Some(Priority::C)));
+        assert!(!priority.matches(None));
+
+        let (found, needle) = filters("priority none");
+        assert_eq!(needle, "");
+        assert!(found.priority.unwrap().matches(None));
+        assert!(!found.priority.unwrap().matches(Some(Priority::D)));
+
+        let (found, needle) = filters("priority review");
+        assert!(found.is_empty());
+        assert_eq!(needle, "priority review");
+    }
+
+    #[test]
+    fn test_duration_filter() {
+        let mut task = Task::new("write docs".into(), crate::Status::Todo);
+        task.estimate = Some(3 * 3600);
+        let (found, needle) = filters("estimate > 1h spent none docs");
+        assert_eq!(needle, "docs");
+        assert!(found.matches(&task));
+        task.time.push(crate::TimeEntry {
+            at: chrono::Utc::now(),
+            seconds: 600,
+        });
+        assert!(!found.matches(&task));
+
+        let (found, _) = filters("оценка <= ~2h");
+        assert!(!found.matches(&task));
+        task.estimate = None;
+        assert!(!found.matches(&task));
+        assert!(filters("estimate none").0.matches(&task));
+
+        task.estimate = parse_duration("1h 30m");
+        for (format, loaded) in round_trip(&[task.clone()]) {
+            assert_eq!(loaded[0].estimate, Some(5400), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_id_list() {
+        assert_eq!(id_list("3,5,7-12"), Some(vec![3..=3, 5..=5, 7..=12]));
+        assert_eq!(id_list("3, 5 - 6"), Some(vec![3..=3, 5..=6]));
+        assert_eq!(id_list("7-12"), Some(vec![7..=12]));
+        assert_eq!(id_list("12"), None);
+        assert_eq!(id_list("12-7"), None);
+        assert_eq!(id_list("3,milk"), None);
+        assert_eq!(id_list("2024-05 report"), None);
+    }
+
     #[test]
     fn test_parse_ru_date() {
         assert_eq!(
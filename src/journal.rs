@@ -0,0 +1,448 @@
+use crate::task::deserialize_comments;
+use crate::{
+    Comment, Priority, Recurrence, Status, Task, TimeEntry, date_str, duration_str, estimate_str,
+    ids_str, parent_str, priority_str, project_str, recurrence_str,
+};
+use chrono::{Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+/// A single change of a single task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    Status {
+        id: usize,
+        before: Status,
+        after: Status,
+    },
+    Title {
+        id: usize,
+        before: String,
+        after: String,
+    },
+    Comments {
+        id: usize,
+        #[serde(deserialize_with = "deserialize_comments")]
+        before: Vec<Comment>,
+        #[serde(deserialize_with = "deserialize_comments")]
+        after: Vec<Comment>,
+    },
+    Priority {
+        id: usize,
+        before: Option<Priority>,
+        after: Option<Priority>,
+    },
+    Due {
+        id: usize,
+        before: Option<NaiveDate>,
+        after: Option<NaiveDate>,
+    },
+    Scheduled {
+        id: usize,
+        before: Option<NaiveDate>,
+        after: Option<NaiveDate>,
+    },
+    Recurrence {
+        id: usize,
+        before: Option<Recurrence>,
+        after: Option<Recurrence>,
+    },
+    Project {
+        id: usize,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    Parent {
+        id: usize,
+        before: Option<usize>,
+        after: Option<usize>,
+    },
+    BlockedBy {
+        id: usize,
+        before: Vec<usize>,
+        after: Vec<usize>,
+    },
+    Estimate {
+        id: usize,
+        before: Option<u64>,
+        after: Option<u64>,
+    },
+    Time {
+        id: usize,
+        before: Vec<TimeEntry>,
+        after: Vec<TimeEntry>,
+    },
+    Add {
+        task: Task,
+    },
+    Remove {
+        task: Task,
+    },
+}
+
+impl Op {
+    /// ID of the changed task
+    pub fn id(&self) -> usize {
+        match self {
+            Op::Status { id, .. }
+            | Op::Title { id, .. }
+            | Op::Comments { id, .. }
+            | Op::Priority { id, .. }
+            | Op::Due { id, .. }
+            | Op::Scheduled { id, .. }
+            | Op::Recurrence { id, .. }
+            | Op::Project { id, .. }
+            | Op::Parent { id, .. }
+            | Op::BlockedBy { id, .. }
+            | Op::Estimate { id, .. }
+            | Op::Time { id, .. } => *id,
+            Op::Add { task } | Op::Remove { task } => task.id,
+        }
+    }
+
+    pub fn renumber(&mut self, from: usize, to: usize) {
+        let id = match self {
+            Op::Status { id, .. }
+            | Op::Title { id, .. }
+            | Op::Comments { id, .. }
+            | Op::Priority { id, .. }
+            | Op::Due { id, .. }
+            | Op::Scheduled { id, .. }
+            | Op::Recurrence { id, .. }
+            | Op::Project { id, .. }
+            | Op::Parent { id, .. }
+            | Op::BlockedBy { id, .. }
+            | Op::Estimate { id, .. }
+            | Op::Time { id, .. } => id,
+            Op::Add { task } | Op::Remove { task } => &mut task.id,
+        };
+        if *id == from {
+            *id = to;
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Op::Status { id, before, after } => Op::Status {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Title { id, before, after } => Op::Title {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Comments { id, before, after } => Op::Comments {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Priority { id, before, after } => Op::Priority {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Due { id, before, after } => Op::Due {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Scheduled { id, before, after } => Op::Scheduled {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Recurrence { id, before, after } => Op::Recurrence {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Project { id, before, after } => Op::Project {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Parent { id, before, after } => Op::Parent {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::BlockedBy { id, before, after } => Op::BlockedBy {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Estimate { id, before, after } => Op::Estimate {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Time { id, before, after } => Op::Time {
+                id,
+                before: after,
+                after: before,
+            },
+            Op::Add { task } => Op::Remove { task },
+            Op::Remove { task } => Op::Add { task },
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Status { id, before, after } => write!(f, "{id}: {before} -> {after}"),
+            Op::Title { id, before, after } => write!(f, "{id}: renamed {before:?} -> {after:?}"),
+            Op::Comments { id, .. } => write!(f, "{id}: comments changed"),
+            Op::Priority { id, before, after } => write!(
+                f,
+                "{id}: priority {} -> {}",
+                priority_str(*before),
+                priority_str(*after)
+            ),
+            Op::Due { id, before, after } => {
+                write!(f, "{id}: due {} -> {}", date_str(*before), date_str(*after))
+            }
+            Op::Scheduled { id, before, after } => write!(
+                f,
+                "{id}: scheduled {} -> {}",
+                date_str(*before),
+                date_str(*after)
+            ),
+            Op::Recurrence { id, before, after } => write!(
+                f,
+                "{id}: repeat {} -> {}",
+                recurrence_str(*before),
+                recurrence_str(*after)
+            ),
+            Op::Project { id, before, after } => write!(
+                f,
+                "{id}: moved {} -> {}",
+                project_str(before.as_deref()),
+                project_str(after.as_deref())
+            ),
+            Op::Parent { id, before, after } => write!(
+                f,
+                "{id}: parent {} -> {}",
+                parent_str(*before),
+                parent_str(*after)
+            ),
+            Op::BlockedBy { id, before, after } => write!(
+                f,
+                "{id}: blocked by {} -> {}",
+                ids_str(before),
+                ids_str(after)
+            ),
+            Op::Estimate { id, before, after } => write!(
+                f,
+                "{id}: estimate {} -> {}",
+                estimate_str(*before),
+                estimate_str(*after)
+            ),
+            Op::Time { id, before, after } => {
+                let spent = |time: &[TimeEntry]| time.iter().map(|entry| entry.seconds).sum();
+                write!(
+                    f,
+                    "{id}: spent {} -> {}",
+                    duration_str(spent(before)),
+                    duration_str(spent(after))
+                )
+            }
+            Op::Add { task } => write!(f, "added {task}"),
+            Op::Remove { task } => write!(f, "removed {task}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    /// Changes made by a single command
+    Do { ops: Vec<Op> },
+    /// Reverts the `Do` entry with the given sequence number
+    Undo { target: u64 },
+    /// Applies the undone `Do` entry with the given sequence number again
+    Redo { target: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub at: chrono::DateTime<Utc>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let at = self.at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        match &self.action {
+            Action::Do { ops } => {
+                write!(f, "#{} {at}", self.seq)?;
+                for op in ops {
+                    write!(f, "\n    {op}")?;
+                }
+                Ok(())
+            }
+            Action::Undo { target } => write!(f, "#{} {at} undo #{target}", self.seq),
+            Action::Redo { target } => write!(f, "#{} {at} redo #{target}", self.seq),
+        }
+    }
+}
+
+/// Append-only log of all changes made to the tasks file. Lives next to the
+/// tasks file with `.journal` suffix, one JSON entry per line.
+pub struct Journal {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let mut entries = vec![];
+        match File::open(&path) {
+            Ok(file) => {
+                for (n, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line) {
+                        Ok(entry) => entries.push(entry),
+                        Err(err) => log::error!("{path:?}:{}: skipping entry: {err}", n + 1),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(Self { path, entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn append(&mut self, action: Action) -> io::Result<&Entry> {
+        let entry = Entry {
+            seq: self.entries.last().map_or(0, |e| e.seq) + 1,
+            at: Utc::now(),
+            action,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        self.entries.push(entry);
+        Ok(self.entries.last().unwrap())
+    }
+
+    fn find(&self, seq: u64) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.seq == seq)
+    }
+
+    /// Replays the journal and returns `(applied, undone)` stacks of `Do`
+    /// entries: the last element of `applied` is the next to undo, the last
+    /// element of `undone` is the next to redo.
+    fn stacks(&self) -> (Vec<&Entry>, Vec<&Entry>) {
+        let mut applied = vec![];
+        let mut undone = vec![];
+        for entry in &self.entries {
+            match entry.action {
+                Action::Do { .. } => {
+                    applied.push(entry);
+                    undone.clear();
+                }
+                Action::Undo { target } => {
+                    if applied.last().is_some_and(|e| e.seq == target) {
+                        undone.extend(applied.pop());
+                    }
+                }
+                Action::Redo { target } => {
+                    if undone.last().is_some_and(|e| e.seq == target) {
+                        applied.extend(undone.pop());
+                    }
+                }
+            }
+        }
+        (applied, undone)
+    }
+
+    /// Up to `n` latest applied entries, the latest first
+    pub fn to_undo(&self, n: usize) -> Vec<&Entry> {
+        self.stacks().0.into_iter().rev().take(n).collect()
+    }
+
+    /// Up to `n` latest undone entries, the latest undone first
+    pub fn to_redo(&self, n: usize) -> Vec<&Entry> {
+        self.stacks().1.into_iter().rev().take(n).collect()
+    }
+
+    /// Operations of the `Do` entry with the given sequence number
+    pub fn ops(&self, seq: u64) -> &[Op] {
+        match self.find(seq).map(|e| &e.action) {
+            Some(Action::Do { ops }) => ops,
+            _ => &[],
+        }
+    }
+}
+
+/// The journal lives next to the tasks file with `.journal` suffix
+pub fn journal_path(filename: &Path) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push(".journal");
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(id: usize) -> Action {
+        Action::Do {
+            ops: vec![Op::Status {
+                id,
+                before: Status::Todo,
+                after: Status::Done,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_undo_redo_stacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_path(&dir.path().join("tasks.csv"));
+        let mut journal = Journal::load(path.clone()).unwrap();
+        journal.append(ops(1)).unwrap();
+        journal.append(ops(2)).unwrap();
+        journal.append(ops(3)).unwrap();
+        journal.append(Action::Undo { target: 3 }).unwrap();
+        journal.append(Action::Undo { target: 2 }).unwrap();
+        journal.append(Action::Redo { target: 2 }).unwrap();
+
+        let journal = Journal::load(path).unwrap();
+        let seqs = |entries: Vec<&Entry>| entries.iter().map(|e| e.seq).collect::<Vec<_>>();
+        assert_eq!(seqs(journal.to_undo(5)), [2, 1]);
+        assert_eq!(seqs(journal.to_redo(5)), [3]);
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::load(dir.path().join("j")).unwrap();
+        journal.append(ops(1)).unwrap();
+        journal.append(Action::Undo { target: 1 }).unwrap();
+        journal.append(ops(2)).unwrap();
+        assert!(journal.to_redo(1).is_empty());
+    }
+}
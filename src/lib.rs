@@ -0,0 +1,23 @@
+//! Tasks storage and search behind the `todo` command line tool: load a
+//! tasks file with [`Tasks::load`], find tasks with [`Tasks::find`] or
+//! [`Tasks::query`], change them and write them back with [`Tasks::save`].
+
+pub mod config;
+pub mod doctor;
+mod error;
+pub mod filter_parser;
+pub mod journal;
+pub mod lock;
+pub mod search;
+pub mod storage;
+mod task;
+mod tasks;
+pub mod timer;
+
+pub use crate::error::{Error, Result};
+pub use crate::task::{
+    Change, Comment, CommentEdit, Event, Priority, Recurrence, Status, Task, TimeEntry, date_str,
+    duration_str, estimate_str, ids_str, parent_str, priority_str, project_str, recurrence_str,
+    tag_of,
+};
+pub use crate::tasks::{Found, Idx, Loc, PathSource, Stopped, TODO_DIR, Tasks};
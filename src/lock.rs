@@ -0,0 +1,91 @@
+use crate::{Error, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockMode {
+    /// Many readers may hold the lock at the same time
+    Shared,
+    /// Held by a single writer from load to save
+    Exclusive,
+}
+
+/// An advisory lock on a file next to the tasks file. The lock is released
+/// when the value is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Waits until the lock is acquired or `TODO_LOCK_TIMEOUT` (seconds) passes
+    pub fn acquire(path: &Path, mode: LockMode) -> Result<Self> {
+        Self::acquire_timeout(path, mode, timeout())
+    }
+
+    pub fn acquire_timeout(path: &Path, mode: LockMode, timeout: Duration) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        let deadline = Instant::now() + timeout;
+        log::debug!("acquiring {mode:?} lock on {path:?}");
+        loop {
+            let result = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+            match result {
+                Ok(()) => {
+                    log::debug!("{mode:?} lock on {path:?} acquired");
+                    return Ok(Self { _file: file });
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                    return Err(Error::Locked {
+                        path: path.to_path_buf(),
+                        timeout,
+                    });
+                }
+                Err(TryLockError::WouldBlock) => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+/// The lock file lives next to the tasks file with `.lock` suffix
+pub fn lock_path(filename: &Path) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push(".lock");
+    name.into()
+}
+
+fn timeout() -> Duration {
+    env::var("TODO_LOCK_TIMEOUT")
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_lock_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv.lock");
+        let _shared = FileLock::acquire_timeout(&path, LockMode::Shared, Duration::ZERO).unwrap();
+        let _another = FileLock::acquire_timeout(&path, LockMode::Shared, Duration::ZERO).unwrap();
+        let err =
+            FileLock::acquire_timeout(&path, LockMode::Exclusive, Duration::ZERO).unwrap_err();
+        assert!(matches!(err, Error::Locked { .. }));
+    }
+}
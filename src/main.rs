@@ -1,37 +1,30 @@
-mod filter_parser;
+mod output;
 
-use crate::filter_parser::Attr;
+use crate::output::{
+    CliError, ConfigRecord, ConvertRecord, EventRecord, HistoryRecord, IssueRecord, OutputFormat,
+    PathRecord, Printer, ProjectRecord, TagRecord, TimeRecord,
+};
 use atty::Stream;
-use chrono::{Local, Utc};
-use clap::{Parser, Subcommand};
-use csv::{ReaderBuilder, WriterBuilder};
-use homedir::my_home;
-use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
-use std::cmp::{Ordering, PartialEq};
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Read, Write, stdin};
+use chrono::{Datelike, Local, NaiveDate, TimeDelta, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Read, Write, stdin};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
-use std::process::Command as Cmd;
-use std::str::FromStr;
-use std::{env, fmt, fs, io};
-use strsim::jaro_winkler;
-
-static TRANSLIT_MAP: Lazy<HashMap<char, char>> = Lazy::new(|| {
-    const ENG: &str = "qwertyuiop[]asdfghjkl;'zxcvbnm,./";
-    const RUS: &str = "йцукенгшщзхъфывапролджэячсмитьбю.";
-
-    ENG.chars().zip(RUS.chars()).collect()
-});
-
-fn translate(input: &str) -> String {
-    input
-        .chars()
-        .map(|c| TRANSLIT_MAP.get(&c).copied().unwrap_or(c))
-        .collect()
-}
+use std::process::{Command as Cmd, ExitCode};
+use std::{env, fs, io};
+use std::{fmt, slice};
+use termimad::crossterm::style::Stylize;
+use todo::config::{self, ColorMode, Config, ConfigPaths};
+use todo::filter_parser;
+use todo::journal::Op;
+use todo::lock::LockMode;
+use todo::search::StringExt;
+use todo::storage::Format;
+use todo::{
+    CommentEdit, Found, Idx, Loc, Priority, Status, Stopped, Task, Tasks, TimeEntry, duration_str,
+    project_str,
+};
 
 fn read_line() -> io::Result<String> {
     let mut buf = vec![];
@@ -41,33 +34,33 @@ fn read_line() -> io::Result<String> {
 }
 
 fn get_editor() -> Option<String> {
-    env::var("EDITOR")
-        .ok()
-        .and_then(|x| x.not_empty())
+    let config = config::get();
+    config
+        .editor
+        .clone()
+        .not_empty()
+        .or_else(|| env::var("EDITOR").ok().and_then(|x| x.not_empty()))
         .or_else(|| env::var("VISUAL").ok().and_then(|x| x.not_empty()))
         .or_else(|| {
-            ["nvim", "vim", "vi", "nano"]
+            config
+                .editors
                 .iter()
-                .find(|&&e| which::which(e).is_ok())
-                .map(|s| s.to_string())
+                .find(|e| which::which(e).is_ok())
+                .cloned()
         })
 }
 
-enum Multiline {
-    Append(String),
-    Full(String),
-}
-
-fn read_multiline(initial: &str) -> io::Result<Multiline> {
+/// Opens the editor with `initial` text, reads piped stdin otherwise
+fn read_multiline(initial: &str) -> io::Result<String> {
     Ok(match (atty::is(Stream::Stdin), get_editor()) {
-        (true, Some(editor)) => Multiline::Full({
+        (true, Some(editor)) => {
             let mut tmp_file = tempfile::Builder::new().suffix(".md").tempfile()?;
             write!(tmp_file, "{}", initial)?;
             let path = tmp_file.path();
             Cmd::new(editor).arg(path).status()?;
-            fs::read_to_string(path)?
-        }),
-        (is_tty, _) => Multiline::Append({
+            fs::read_to_string(path)?.trim().to_string()
+        }
+        (is_tty, _) => {
             let mut buf = vec![];
             let mut handle = stdin().lock();
             if is_tty {
@@ -79,55 +72,66 @@ fn read_multiline(initial: &str) -> io::Result<Multiline> {
                 handle.read_to_end(&mut buf)?;
             }
             String::from_utf8_lossy(&buf).trim().to_string()
-        }),
-    })
-}
-
-trait StringExt {
-    fn contains_all<T: AsRef<str>>(&self, i: impl IntoIterator<Item = T>) -> bool;
-    fn not_empty(self) -> Option<Self>
-    where
-        Self: Sized;
-}
-
-impl<T> StringExt for T
-where
-    T: AsRef<str>,
-{
-    fn contains_all<Item: AsRef<str>>(&self, i: impl IntoIterator<Item = Item>) -> bool {
-        i.into_iter().all(|x| self.as_ref().contains(x.as_ref()))
-    }
-
-    fn not_empty(self) -> Option<Self> {
-        if self.as_ref().is_empty() {
-            None
-        } else {
-            Some(self)
         }
-    }
+    })
 }
 
 #[derive(Parser)]
 #[command(name = "todo")]
 struct TodoCli {
+    /// Output format, `table` is for humans
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+    /// Fail instead of asking which task to use when several tasks match.
+    /// Enabled when stdin is not a terminal.
+    #[arg(long, global = true)]
+    non_interactive: bool,
+    /// Use the best matching task when several tasks match
+    #[arg(long, global = true, conflicts_with = "all")]
+    first: bool,
+    /// Apply the command to every matching task
+    #[arg(long, global = true)]
+    all: bool,
+    /// Treat the task as a `find` expression (date filters included) and
+    /// apply the command to every match after a confirmation
+    #[arg(long, global = true, conflicts_with_all = ["first", "all"])]
+    query: bool,
+    /// The project to list and add tasks to, `TODO_PROJECT` by default.
+    /// Without it the default project is used.
+    #[arg(short, long, global = true)]
+    project: Option<String>,
+    /// List and find tasks of every project, grouped by project
+    #[arg(long, global = true)]
+    all_projects: bool,
     #[command(subcommand)]
     command: Option<Command>,
 }
 
 #[derive(Subcommand)]
 enum Command {
-    /// Print `todo` and `done` tasks lists
+    /// Print `todo` and `done` tasks lists. A status and `#tag`s narrow the
+    /// list: `todo list done '#work'`
     #[clap(visible_aliases = &["l", "ls"])]
-    List { status: Option<String> },
+    List {
+        filter: Vec<String>,
+        /// Sum estimates and spent time under each status
+        #[arg(long)]
+        totals: bool,
+    },
     /// Change status to `todo`
     #[clap(visible_aliases = &["t", "recover"])]
     Todo { task: Vec<String> },
-    /// Change status to `done`
+    /// Change status to `done`. A task with `todo` subtasks cannot be done.
     #[clap(visible_alias = "dn")]
-    Done { task: Vec<String> },
+    Done {
+        task: Vec<String>,
+        /// Mark done even if some subtasks are not done
+        #[arg(long)]
+        force: bool,
+    },
     /// Remove a task. If the task in `todo` or `done` status, soft-deletes it
     /// (set `drop` status). If the task is already in `drop` status, physically
-    /// removes it.
+    /// removes it. Subtasks are dropped and removed along after a confirmation.
     #[clap(visible_aliases = &["remove", "delete", "rm"])]
     Drop { task: Vec<String> },
     /// Rename a task
@@ -139,687 +143,1617 @@ enum Command {
     /// Show a task's details and comments
     #[clap(visible_alias = "d")]
     Detail { task: Vec<String> },
-    /// Add a comment to a task
+    /// Show the history of a task's changes
+    LogOf { task: Vec<String> },
+    /// Add a comment to a task, from the editor or piped stdin
     #[clap(visible_alias = "c")]
-    Comment { task: Vec<String> },
+    Comment {
+        task: Vec<String>,
+        /// Change the comment with this number, see `todo detail`
+        #[arg(long, conflicts_with = "delete")]
+        edit: Option<usize>,
+        /// Delete the comment with this number
+        #[arg(long)]
+        delete: Option<usize>,
+    },
+    /// Set a task's priority: the last word is the level, `a`..`d`, `high`,
+    /// `medium`, `low`, `lowest` or `none`
+    #[clap(visible_alias = "p")]
+    Prio { task: Vec<String> },
+    /// Set a task's due date: `todo due 12 next friday`, `none` clears it
+    Due { task: Vec<String> },
+    /// Set the time a task is expected to take: `todo estimate 12 2h`, `none`
+    /// clears it
+    Estimate { task: Vec<String> },
+    /// Set the date to start working on a task: `todo schedule 12 tomorrow`,
+    /// `none` clears it
+    Schedule { task: Vec<String> },
+    /// Make a task recurring: `todo repeat 12 every friday`, `none` stops it.
+    /// Marking a recurring task done creates its next occurrence.
+    Repeat { task: Vec<String> },
+    /// Add and remove a task's tags: `todo tag 12 +work -home`
+    Tag {
+        #[arg(allow_hyphen_values = true)]
+        task: Vec<String>,
+    },
+    /// Print tags with the number of `todo` and `done` tasks
+    Tags,
+    /// Make a task wait for another one: `todo block 12 on 7`. Marking the
+    /// blocker done unblocks the task.
+    Block { task: Vec<String> },
+    /// Stop a task waiting for another one: `todo unblock 12 on 7`, for every
+    /// task without `on`
+    Unblock { task: Vec<String> },
+    /// Print `todo` tasks that don't wait for unfinished tasks
+    #[clap(visible_alias = "n")]
+    Next,
+    /// Start tracking time spent on a task, the running timer is stopped first
+    Start { task: Vec<String> },
+    /// Stop the running timer, the time goes to its task
+    Stop,
+    /// Add time spent on a task by hand: `todo spent 12 1h30m`
+    Spent { task: Vec<String> },
+    /// Print summaries of the tasks in the scope
+    Report {
+        #[command(subcommand)]
+        command: ReportCommand,
+    },
+    /// Create new task, the same as `todo <task>`
+    #[clap(visible_alias = "a")]
+    Add {
+        task: Vec<String>,
+        /// Add a subtask of this task (an ID or a search), it goes to the
+        /// parent's project
+        #[arg(long)]
+        under: Option<String>,
+    },
+    /// Move a task to another project, `--to none` moves it to the default one
+    #[clap(visible_alias = "mv")]
+    Move {
+        task: Vec<String>,
+        #[arg(long)]
+        to: String,
+    },
+    /// Print projects with the number of `todo` tasks
+    Projects,
     /// Create new task in `done` status
     Log { task: Vec<String> },
     /// Physically remove all tasks in `drop` status
     RemoveDropped,
     /// Soft-delete all done tasks (set `drop` status)
     DropDone,
-    /// Print the tasks file path
+    /// Print the tasks file path, the reason it's used goes to stderr
     #[clap(visible_alias = "w")]
     Where,
+    /// Create `.todo/tasks.csv` in the current directory to keep its own tasks
+    Init,
+    /// Show and change settings of `~/.config/todo/config.toml` and
+    /// `.todo/config.toml` of the project directory
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Revert the last `n` (1 by default) changes
+    Undo { n: Option<usize> },
+    /// Apply again the last `n` (1 by default) undone changes
+    Redo { n: Option<usize> },
+    /// Print the last `n` (10 by default) changes
+    History { n: Option<usize> },
+    /// Check the tasks file for broken tasks, duplicate IDs and invalid
+    /// timestamps
+    Doctor {
+        /// Fix found problems
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Copy all tasks to a file of another format
+    Convert {
+        /// csv, jsonl, toml or sqlite
+        #[arg(long)]
+        to: String,
+        /// Defaults to the tasks file path with the format's extension
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
     /// Create new task
     #[clap(external_subcommand)]
     External(Vec<String>),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Hash, Eq, Copy)]
-#[serde(rename_all = "lowercase")]
-enum Status {
-    Todo,
-    Done,
-    Drop,
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print every setting with its current value
+    #[clap(visible_alias = "ls")]
+    List,
+    /// Print a setting, nested ones are dotted: `search.similar_word`
+    Get { key: String },
+    /// Change a setting in the global config
+    Set {
+        key: String,
+        value: String,
+        /// Change the project directory config instead
+        #[arg(long)]
+        local: bool,
+    },
+    /// Remove a setting from the global config, the default is used again
+    Unset {
+        key: String,
+        /// Change the project directory config instead
+        #[arg(long)]
+        local: bool,
+    },
 }
 
-impl Status {
-    const ALL: &'static [Self] = &[Self::Drop, Self::Done, Self::Todo];
-    const VISIBLE: &'static [Self] = &[Self::Done, Self::Todo];
-
-    fn is_visible(self) -> bool {
-        match self {
-            Status::Todo | Status::Done => true,
-            Status::Drop => false,
-        }
-    }
+#[derive(Subcommand)]
+enum ReportCommand {
+    /// Time spent within a date range: `todo report time last week --by tag`
+    Time {
+        /// `last week`, `from 2025-05-01 to 2025-05-31`, all time by default
+        range: Vec<String>,
+        #[arg(long, value_enum, default_value_t)]
+        by: GroupBy,
+    },
 }
 
-impl FromStr for Status {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "todo" => Ok(Status::Todo),
-            "done" => Ok(Status::Done),
-            "drop" => Ok(Status::Drop),
-            _ => Err(()),
-        }
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+enum GroupBy {
+    #[default]
+    Day,
+    Week,
+    /// A task with several tags counts for each of them
+    Tag,
 }
 
-impl Display for Status {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{self:?}")
-    }
+/// What to do when several tasks match
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Pick {
+    /// Ask the user to choose one
+    Ask,
+    /// Fail with the list of candidates
+    Fail,
+    /// Take the best match
+    First,
+    /// Take all of them
+    All,
+    /// Take all tasks matching a `find` expression
+    Query,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Task {
-    id: usize,
-    status: Status,
-    title: String,
-    created_at: chrono::DateTime<Utc>,
-    updated_at: chrono::DateTime<Utc>,
-    comments: String,
-}
-
-impl Display for Task {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}. {}", self.id, self.title)?;
-        if !self.comments.trim().is_empty() {
-            write!(f, " [*]")?;
-        }
-        Ok(())
-    }
-}
-
-impl Task {
-    fn details(&self) -> Result<String, fmt::Error> {
-        use std::fmt::Write;
-
-        let mut buf = String::with_capacity(128);
-        writeln!(buf, "Title: {}", self.title)?;
-        writeln!(buf, "ID: {}", self.id)?;
-        writeln!(buf, "Status: {}", self.status)?;
-        writeln!(
-            buf,
-            "created at: {:?}",
-            self.created_at.with_timezone(&Local)
-        )?;
-        writeln!(
-            buf,
-            "updated at: {:?}",
-            self.updated_at.with_timezone(&Local)
-        )?;
-        if !self.comments.is_empty() {
-            writeln!(buf, "{}", termimad::term_text("------------------------"))?;
-            writeln!(buf, "{}", termimad::term_text(&self.comments))?;
-        }
-        Ok(buf)
-    }
-
-    fn change_title(&mut self, new_title: String) {
-        self.title = new_title;
-        self.updated_at = Utc::now();
-    }
-
-    fn add_comment(&mut self, comment: Multiline) {
-        let old = self.comments.clone();
-        match comment {
-            Multiline::Append(comment) => {
-                if !comment.is_empty() {
-                    if !self.comments.is_empty() {
-                        self.comments.push('\n');
-                    }
-                    self.comments.push_str(&comment);
-                }
-            }
-            Multiline::Full(comment) => self.comments = comment,
-        }
-
-        if self.comments != old {
-            self.updated_at = Utc::now();
+impl Pick {
+    fn new(cli: &TodoCli) -> Self {
+        if cli.query {
+            Pick::Query
+        } else if cli.all {
+            Pick::All
+        } else if cli.first {
+            Pick::First
+        } else if cli.non_interactive || !cli.format.is_table() || !atty::is(Stream::Stdin) {
+            Pick::Fail
+        } else {
+            Pick::Ask
         }
     }
-
-    fn set_status(&mut self, status: Status) {
-        self.status = status;
-        self.updated_at = Utc::now();
-    }
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-struct Idx(usize);
-
-impl From<usize> for Idx {
-    fn from(value: usize) -> Self {
-        Self(value)
-    }
 }
 
-impl From<Idx> for usize {
-    fn from(value: Idx) -> Self {
-        value.0
+/// A project name given by the user, `none` is the default project
+fn project_name(name: &str) -> Option<String> {
+    match name.trim() {
+        "" | "none" => None,
+        name => Some(name.to_string()),
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-struct Loc {
-    idx: Idx,
-    id: usize,
+/// Which tasks `list` and `find` show and where new tasks go
+struct Scope {
+    /// The current project, `None` is the default one
+    project: Option<String>,
+    /// Show tasks of every project
+    all: bool,
 }
 
-impl Loc {
-    fn new<I: Into<Idx>>(idx: I, id: usize) -> Self {
+impl Scope {
+    fn new(cli: &TodoCli) -> Self {
+        let project = match &cli.project {
+            Some(name) => project_name(name),
+            None => env::var("TODO_PROJECT")
+                .ok()
+                .and_then(|name| project_name(&name)),
+        };
+        log::debug!("current project is {project:?}");
         Self {
-            idx: idx.into(),
-            id,
+            project,
+            all: cli.all_projects,
         }
     }
-}
 
-struct Tasks {
-    inner: Vec<Task>,
-    filename: PathBuf,
+    fn contains(&self, task: &Task) -> bool {
+        self.all || task.project == self.project
+    }
 }
 
-impl Tasks {
-    fn default_path() -> PathBuf {
-        if let Some((_, value)) =
-            env::vars().find(|(key, value)| key == "TASKS_FILE" && !value.trim().is_empty())
-        {
-            log::debug!("TASKS_FILE was found: {value:?}");
-            return value.trim().into();
+/// Finds the tasks the command is about: explicit IDs (`3,5,7-12`) or the
+/// best matches, several matches are resolved according to `pick`
+fn select<'a>(
+    tasks: &'a Tasks,
+    needle: &str,
+    show_dropped: bool,
+    out: &Printer,
+    pick: Pick,
+) -> Result<Vec<Found<'a>>, CliError> {
+    let candidates = if pick == Pick::Query {
+        let mut found = tasks.query(needle);
+        found.retain(|found| show_dropped || found.task.status.is_visible());
+        found
+    } else if let Some(ids) = filter_parser::id_list(needle) {
+        let found = tasks.find_ids(&ids, show_dropped);
+        for id in ids.iter().filter(|range| range.start() == range.end()) {
+            if !found.iter().any(|f| id.contains(&f.loc.id)) {
+                eprintln!("No task with ID {}", id.start());
+            }
         }
-        let mut file = my_home()
-            .transpose()
-            .unwrap()
-            .expect("cannot determine home directory");
-        file.push(".todo");
-        file.push("tasks.csv");
-        file
-    }
-    fn load_default() -> io::Result<Self> {
-        Self::load(Self::default_path())
-    }
-
-    fn load(filename: PathBuf) -> io::Result<Self> {
-        log::info!("loading tasks from {filename:?}");
-        if let Some(dir) = filename.parent() {
-            fs::create_dir_all(dir)?;
+        return match found.is_empty() {
+            true => Err(CliError::NotFound),
+            false => Ok(found),
+        };
+    } else {
+        tasks.find(needle, show_dropped, false)
+    };
+    match (candidates.as_slice(), pick) {
+        ([], _) => Err(CliError::NotFound),
+        ([_], _) | (_, Pick::All | Pick::Query) => Ok(candidates),
+        (many, Pick::First) => {
+            // Candidates are ordered from the best, the earliest task wins a tie
+            let best = many.iter().min_by_key(|found| found.candidate).copied();
+            Ok(best.into_iter().collect())
         }
-        let file = File::open(&filename).or_else(|_| {
-            OpenOptions::new()
-                .create(true)
-                .truncate(false)
-                .write(true)
-                .read(true)
-                .open(&filename)
-        })?;
-        let reader = BufReader::new(file);
-        let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(reader);
-        let mut tasks = vec![];
-        for r in rdr.deserialize() {
-            tasks.push(r?);
+        (many, Pick::Ask) => {
+            println!("Select ID:");
+            let shown = many.iter().map(|found| found.task);
+            match show_dropped {
+                true => print_all_tasks(tasks, shown),
+                false => print_visible_tasks(tasks, shown),
+            };
+            let id: Option<usize> = read_line()?.parse().ok();
+            // Despite the fact this id may exist, we force user to choose only
+            // over the list we printed to prevent mistakes
+            let chosen = many
+                .iter()
+                .find(|found| Some(found.loc.id) == id)
+                .copied()
+                .ok_or(CliError::NotFound)?;
+            Ok(vec![chosen])
+        }
+        (many, Pick::Fail) => {
+            eprintln!("Several tasks match:");
+            for found in many {
+                eprintln!("{}", found.task);
+            }
+            let records: Vec<_> = many.iter().map(|found| out.found(found)).collect();
+            out.write(&records)?;
+            Err(CliError::Ambiguous(many.len()))
         }
-        Ok(Self {
-            inner: tasks,
-            filename,
-        })
     }
+}
 
-    fn set_status_idx(&mut self, idx: Idx, status: Status) -> Option<&Task> {
-        let task = self.find_idx_mut(idx)?;
-        task.set_status(status);
-        Some(task)
+/// Like [`select`] for commands that change a single task only
+fn select_one<'a>(
+    tasks: &'a Tasks,
+    needle: &str,
+    show_dropped: bool,
+    out: &Printer,
+    pick: Pick,
+) -> Result<Found<'a>, CliError> {
+    if matches!(pick, Pick::All | Pick::Query) {
+        return Err(CliError::Usage(
+            "--all and --query cannot be used with this command",
+        ));
     }
-
-    fn set_done_idx(&mut self, idx: Idx) -> Option<&Task> {
-        self.set_status_idx(idx, Status::Done)
+    match select(tasks, needle, show_dropped, out, pick)?.as_slice() {
+        [] => Err(CliError::NotFound),
+        [one] => Ok(*one),
+        _ => Err(CliError::Usage("this command changes a single task only")),
     }
+}
 
-    fn set_todo_idx(&mut self, idx: Idx) -> Option<&Task> {
-        self.set_status_idx(idx, Status::Todo)
+/// Shows the tasks matched by `--query` and asks whether to change them
+fn confirm_batch(out: &Printer, pick: Pick, found: &[Found]) -> bool {
+    if pick != Pick::Query || !config::get().confirm {
+        return true;
     }
-
-    fn set_dropped_idx(&mut self, idx: Idx) -> Option<&Task> {
-        self.set_status_idx(idx, Status::Drop)
+    out.prompt(format_args!("{} tasks will be changed:", found.len()));
+    for found in found {
+        out.prompt(found.task);
     }
+    confirm(out)
+}
 
-    fn remove_dropped(&mut self) -> usize {
-        let orig_len = self.inner.len();
-        self.inner.retain(|t| t.status.is_visible());
-        let new_len = self.inner.len();
-        orig_len - new_len
-    }
+fn details(tasks: &Tasks, task: &Task) -> Result<String, fmt::Error> {
+    use std::fmt::Write;
 
-    fn drop_done(&mut self) -> usize {
-        let mut dropped = 0;
-        self.inner.iter_mut().for_each(|task| {
-            if task.status == Status::Done {
-                task.set_status(Status::Drop);
-                dropped += 1
-            }
-        });
-        dropped
+    let mut buf = String::with_capacity(128);
+    writeln!(buf, "Title: {}", task.title)?;
+    writeln!(buf, "ID: {}", task.id)?;
+    writeln!(buf, "Status: {}", task.status)?;
+    if let Some(project) = &task.project {
+        writeln!(buf, "Project: {project}")?;
     }
-
-    fn remove(&mut self, idx: Idx) -> Option<Task> {
-        let idx = idx.into();
-        if idx < self.inner.len() {
-            Some(self.inner.remove(idx))
-        } else {
-            None
+    if let Some(parent) = task.parent {
+        match tasks.iter().find(|t| t.id == parent) {
+            Some(parent) => writeln!(buf, "Parent: {parent}")?,
+            None => writeln!(buf, "Parent: {parent}")?,
         }
     }
-
-    fn next_loc(&self) -> Loc {
-        // We might have assumed the last vec element is the latest hence has the
-        // greatest ID, but the tasks file may be externally shuffled so seq scan
-        // is the only option.
-        let next_id = self.inner.iter().map(|t| t.id).max().unwrap_or(0) + 1;
-        let next_idx = self.inner.len();
-        Loc::new(next_idx, next_id)
+    if let Some((done, total)) = tasks.progress(task.id) {
+        writeln!(buf, "Subtasks: {done}/{total} done")?;
     }
-
-    fn add(&mut self, title: String, status: Status) -> Loc {
-        let loc = self.next_loc();
-        debug_assert_eq!(loc.idx, self.inner.len().into());
-        let task = Task {
-            id: loc.id,
-            title,
-            comments: String::new(),
-            status,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        };
-        self.inner.push(task);
-        loc
+    if let Some(priority) = task.priority {
+        writeln!(buf, "Priority: {priority}")?;
     }
-
-    fn save(&self) -> io::Result<()> {
-        let buf = {
-            log::debug!("writing tasks to buffer before saving to file");
-            let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
-            for record in &self.inner {
-                wtr.serialize(record)?;
-            }
-            wtr.into_inner()
-                .map_err(|_| io::Error::other("cannot flush the buffer"))?
-        };
-        if let Some(dir) = self.filename.parent() {
-            fs::create_dir_all(dir)?;
-        }
-        let mut file = File::create(&self.filename)?;
-        file.write_all(&buf)?;
-        log::info!("file saved");
-        Ok(())
+    let config = config::get();
+    if let Some(scheduled) = task.scheduled {
+        writeln!(buf, "Scheduled: {}", config.date(scheduled))?;
     }
-
-    fn find_idx(&self, idx: Idx) -> Option<&Task> {
-        let i: usize = idx.into();
-        self.inner.get(i)
+    if let Some(due) = task.due {
+        writeln!(buf, "Due: {}", config.date(due))?;
     }
-
-    fn find_idx_mut(&mut self, idx: Idx) -> Option<&mut Task> {
-        let i: usize = idx.into();
-        self.inner.get_mut(i)
-    }
-
-    fn find(&self, needle: &str, show_dropped: bool, empty_show_all: bool) -> Vec<(Loc, &Task)> {
-        let needle = needle.trim().to_lowercase();
-        let mut candidates = vec![];
-        if needle.is_empty() {
-            return match empty_show_all {
-                true => self
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, task)| (Loc::new(idx, task.id), task))
-                    .collect(),
-                false => candidates,
-            };
+    if let Some(recurrence) = task.recurrence {
+        writeln!(buf, "Repeats: {recurrence}")?;
+        if let Some(next) = task.next_occurrence(Local::now().date_naive()) {
+            writeln!(buf, "Next: {}", config.date(next))?;
         }
-        log::debug!("searching candidates for '{needle}'");
-        for (idx, task) in self.iter().enumerate() {
-            let candidate = Candidate::check(&needle, task)
-                .or_else(|| Candidate::check(&translate(&needle), task));
-            log::debug!("candidate '{task}' result is {candidate:?}");
-            if let Some(candidate) = candidate {
-                match candidate {
-                    Candidate::ById if show_dropped || task.status.is_visible() => {
-                        log::debug!("searching stopped because ID was found");
-                        return vec![(Loc::new(idx, task.id), task)];
-                    }
-                    _ => candidates.push((Loc::new(idx, task.id), task)),
-                }
-            }
+    }
+    if let Some(estimate) = task.estimate {
+        let spent = task.spent();
+        match estimate.checked_sub(spent) {
+            Some(left) => writeln!(
+                buf,
+                "Estimate: {} ({} left)",
+                duration_str(estimate),
+                duration_str(left)
+            )?,
+            None => writeln!(
+                buf,
+                "Estimate: {} ({} over)",
+                duration_str(estimate),
+                duration_str(spent - estimate)
+            )?,
         }
-        log::debug!("searching complete");
-
-        if !show_dropped {
-            candidates.retain(|(_, t)| t.status.is_visible());
+    }
+    if !task.time.is_empty() {
+        writeln!(buf, "Spent: {}", duration_str(task.spent()))?;
+        for entry in &task.time {
+            let at = entry.at.with_timezone(&Local);
+            writeln!(
+                buf,
+                "    {} {} {}",
+                config.date(at.date_naive()),
+                at.format("%H:%M"),
+                duration_str(entry.seconds)
+            )?;
         }
-        candidates
     }
-
-    fn select_interactive(&self, needle: &str, show_dropped: bool) -> Option<Loc> {
-        let candidates: Vec<_> = self.find(needle, show_dropped, false).into_iter().collect();
-        match candidates.as_slice() {
-            [] => None,
-            [one] => Some(one.0),
-            many => {
-                println!("Select ID:");
-                let tasks = many.iter().map(|(_, x)| *x);
-                match show_dropped {
-                    true => print_all_tasks(tasks),
-                    false => print_visible_tasks(tasks),
-                };
-                let id: usize = read_line().ok()?.parse().ok()?;
-                // Despite the fact this id may exist, we force user to choose only
-                // over the list we printed to prevent mistakes
-                many.iter()
-                    .find_map(|(loc, _)| if loc.id == id { Some(*loc) } else { None })
-            }
+    if let Ok(Some(timer)) = tasks.timer()
+        && timer.id == task.id
+    {
+        let elapsed = duration_str(timer.elapsed(Utc::now()));
+        writeln!(buf, "Timer: running for {elapsed}")?;
+    }
+    writeln!(
+        buf,
+        "created at: {:?}",
+        task.created_at.with_timezone(&Local)
+    )?;
+    writeln!(
+        buf,
+        "updated at: {:?}",
+        task.updated_at.with_timezone(&Local)
+    )?;
+    if !task.history.is_empty() {
+        writeln!(buf, "history:")?;
+        writeln!(buf, "{}", task.history_log()?)?;
+    }
+    if !task.comments.is_empty() {
+        writeln!(buf, "{}", termimad::term_text("------------------------"))?;
+    }
+    for (n, comment) in task.comments.iter().enumerate() {
+        let at = comment.at.with_timezone(&Local);
+        let date = config::get().date(at.date_naive());
+        write!(buf, "#{} {date} {}", n + 1, at.format("%H:%M"))?;
+        if let Some(author) = &comment.author {
+            write!(buf, " {author}")?;
         }
+        writeln!(buf)?;
+        writeln!(buf, "{}", termimad::term_text(&comment.text))?;
     }
+    Ok(buf)
+}
 
-    fn iter(&self) -> impl Iterator<Item = &Task> {
-        self.inner.iter()
-    }
+fn print_visible_tasks<'a>(tasks: &Tasks, shown: impl Iterator<Item = &'a Task> + 'a) {
+    print_only_status_tasks(tasks, shown, Status::VISIBLE, false)
 }
 
-fn print_visible_tasks<'a>(tasks: impl Iterator<Item = &'a Task> + 'a) {
-    print_only_status_tasks(tasks, Status::VISIBLE)
+fn print_all_tasks<'a>(tasks: &Tasks, shown: impl Iterator<Item = &'a Task> + 'a) {
+    print_only_status_tasks(tasks, shown, Status::ALL, false)
 }
 
-fn print_all_tasks<'a>(tasks: impl Iterator<Item = &'a Task> + 'a) {
-    print_only_status_tasks(tasks, Status::ALL)
+/// Orders tasks as a tree: subtasks go right after their parent with the
+/// depth to indent them. A subtask whose parent isn't listed is a top-level
+/// one.
+fn tree<'a>(tasks: &[&'a Task]) -> Vec<(usize, &'a Task)> {
+    let listed: HashSet<_> = tasks.iter().map(|task| task.id).collect();
+    let roots = (0..tasks.len()).filter(|&i| !tasks[i].parent.is_some_and(|p| listed.contains(&p)));
+    let mut ordered = Vec::with_capacity(tasks.len());
+    let mut seen = HashSet::new();
+    // Tasks of a cycle have no root, they are listed at the top level at last
+    for root in roots.chain(0..tasks.len()) {
+        let mut stack = vec![(0, root)];
+        while let Some((depth, i)) = stack.pop() {
+            if !seen.insert(i) {
+                continue;
+            }
+            ordered.push((depth, tasks[i]));
+            let subtasks = (0..tasks.len())
+                .rev()
+                .filter(|&j| tasks[j].parent == Some(tasks[i].id));
+            stack.extend(subtasks.map(|j| (depth + 1, j)));
+        }
+    }
+    ordered
 }
 
+/// Prints tasks grouped by status as trees, parents get the progress of their
+/// subtasks from `tasks`. With `totals` every group ends with the sum of its
+/// estimates and spent time.
 fn print_only_status_tasks<'a>(
-    tasks: impl Iterator<Item = &'a Task> + 'a,
+    tasks: &Tasks,
+    shown: impl Iterator<Item = &'a Task> + 'a,
     only_statuses: &[Status],
+    totals: bool,
 ) {
+    let today = Local::now().date_naive();
+    let config = config::get();
+    let overdue_color = match config.color {
+        ColorMode::Auto if atty::is(Stream::Stdout) => config.overdue_color().ok(),
+        ColorMode::Always => config.overdue_color().ok(),
+        ColorMode::Auto | ColorMode::Never => None,
+    };
     let mut by_status: HashMap<_, Vec<_>> = HashMap::new();
-    for task in tasks {
+    for task in shown {
         by_status.entry(&task.status).or_default().push(task);
     }
     for status in only_statuses {
-        if let Some(status_tasks) = by_status.get(status) {
+        if let Some(status_tasks) = by_status.get_mut(status) {
+            // Stable, so tasks of the same priority stay in file order
+            status_tasks.sort_by_key(|task| (task.priority.is_none(), task.priority));
             println!("[{status}]:");
-            for task in status_tasks {
-                println!("{task}");
+            for (depth, task) in tree(status_tasks) {
+                let mut line = format!("{}{task}", "  ".repeat(depth));
+                if let Some((done, total)) = tasks.progress(task.id) {
+                    line.push_str(&format!(" ({done}/{total} done)"));
+                }
+                if let Some(color) = overdue_color.filter(|_| task.is_overdue(today)) {
+                    println!("{}", line.with(color));
+                } else {
+                    println!("{line}");
+                }
+            }
+            if totals {
+                println!("{}", Totals::of(status_tasks));
             }
         }
     }
 }
 
-fn is_similar_words(needles: &[&str], haystack: &[&str]) -> bool {
-    debug_assert!(needles.iter().all(|w| w.to_lowercase() == *w));
-    debug_assert!(haystack.iter().all(|w| w.to_lowercase() == *w));
+/// Estimated, spent and remaining time of a group of tasks
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Totals {
+    estimate: u64,
+    spent: u64,
+    /// Estimates minus time spent on the estimated tasks
+    remaining: u64,
+}
 
-    let mut weights = Vec::with_capacity(needles.len() + haystack.len());
-    for needle_word in needles {
-        for haystack_word in haystack {
-            weights.push((
-                jaro_winkler(needle_word, haystack_word),
-                needle_word,
-                haystack_word,
-            ));
+impl Totals {
+    fn of(tasks: &[&Task]) -> Self {
+        let mut totals = Self::default();
+        for task in tasks {
+            totals.estimate += task.estimate.unwrap_or(0);
+            totals.spent += task.spent();
+            totals.remaining += task.remaining().unwrap_or(0);
         }
+        totals
     }
-    weights.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less));
-    weights.reverse();
-    if let Some((sim, n, h)) = weights
-        .iter()
-        .find(|(x, needle, title)| x >= &0.999 && (needle.len() >= 3 || title.len() >= 3))
-    {
-        log::debug!("found 99.9%+ similar word: {sim} ({n} x {h})");
-        return true;
-    }
-    let sum: f64 = weights.iter().take(needles.len()).map(|(x, _, _)| x).sum();
-    #[allow(clippy::cast_precision_loss)]
-    let count = (needles.len().saturating_sub(1) + 1) as f64;
-    let avg = sum / count;
-    if avg > 0.85 {
-        log::debug!("average similarity is more than 85%: {avg}");
-        return true;
-    }
-    false
 }
 
-#[derive(Debug, Copy, Clone)]
-enum Candidate {
-    ById,
-    SubsetOfTitle,
-    SimilarTitle,
-    SubsetOfComment,
-    SimilarComment,
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Total: ~{} estimated, {} spent, {} remaining",
+            duration_str(self.estimate),
+            duration_str(self.spent),
+            duration_str(self.remaining)
+        )
+    }
 }
 
-impl Candidate {
-    fn check(needle: &str, task: &Task) -> Option<Self> {
-        debug_assert_eq!(needle, needle.trim().to_lowercase());
-        log::debug!("checking needle '{needle}' against task {task}");
-        if let Ok(id) = needle.parse::<usize>()
-            && task.id == id
-        {
-            return Some(Candidate::ById);
-        }
-
-        let needle_words = needle.split_whitespace().collect::<Vec<_>>();
-        let title = task.title.to_lowercase();
-        if title.contains_all(&needle_words) {
-            return Some(Candidate::SubsetOfTitle);
+/// Prints tasks of the given statuses in the scope, grouped by project if the
+/// scope has every project. `totals` are printed for the table only.
+fn print_tasks<'a>(
+    out: &Printer,
+    scope: &Scope,
+    tasks: &Tasks,
+    shown: impl Iterator<Item = &'a Task> + 'a,
+    only_statuses: &[Status],
+    totals: bool,
+) -> io::Result<()> {
+    let shown: Vec<_> = shown
+        .filter(|task| scope.contains(task) && only_statuses.contains(&task.status))
+        .collect();
+    if out.is_table() && scope.all {
+        let mut by_project: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for task in shown {
+            by_project
+                .entry(task.project.as_deref())
+                .or_default()
+                .push(task);
         }
-
-        if is_similar_words(&needle_words, &title.split_whitespace().collect::<Vec<_>>()) {
-            return Some(Candidate::SimilarTitle);
+        for (project, shown) in by_project {
+            println!("== {} ==", project_str(project));
+            print_only_status_tasks(tasks, shown.into_iter(), only_statuses, totals);
         }
+        return Ok(());
+    }
+    if out.is_table() {
+        print_only_status_tasks(tasks, shown.into_iter(), only_statuses, totals);
+        return Ok(());
+    }
+    let records: Vec<_> = shown.into_iter().map(|task| out.task(task)).collect();
+    out.write(&records)
+}
 
-        if !task.comments.is_empty() {
-            let comment = task.comments.to_lowercase();
-            if comment.contains_all(&needle_words) {
-                return Some(Candidate::SubsetOfComment);
-            }
-            if is_similar_words(
-                &needle_words,
-                &comment.split_whitespace().collect::<Vec<_>>(),
-            ) {
-                return Some(Candidate::SimilarComment);
-            }
-        }
+/// Releases the borrow of the tasks to change them
+fn locs(found: Vec<Found>) -> Vec<Loc> {
+    found.into_iter().map(|found| found.loc).collect()
+}
 
-        None
+/// Changes the tasks matching `needle` one by one, `--query` matches are
+/// confirmed first. Saves and prints the changed tasks under `header`.
+fn mutate(
+    out: &Printer,
+    pick: Pick,
+    needle: &str,
+    show_dropped: bool,
+    (action, header): (&'static str, &str),
+    mut change: impl FnMut(&mut Tasks, Idx) -> todo::Result<Option<&Task>>,
+) -> Result<(), CliError> {
+    let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+    let found = select(&tasks, needle, show_dropped, out, pick)?;
+    if !confirm_batch(out, pick, &found) {
+        return Ok(());
+    }
+    let mut changed = vec![];
+    for loc in locs(found) {
+        changed.extend(change(&mut tasks, loc.idx)?.cloned());
     }
+    tasks.save()?;
+    print_changed(out, action, header, &changed)?;
+    Ok(())
 }
 
-macro_rules! print_not_found {
-    () => {
-        println!("Not found")
-    };
+/// Prints tasks changed by a command, e.g. `Done: 1. buy milk`
+fn print_changed(
+    out: &Printer,
+    action: &'static str,
+    header: &str,
+    tasks: &[Task],
+) -> io::Result<()> {
+    if out.is_table() {
+        for task in tasks {
+            println!("{header}: {task}");
+        }
+        return Ok(());
+    }
+    let records: Vec<_> = tasks
+        .iter()
+        .map(|task| out.task(task).action(action))
+        .collect();
+    out.write(&records)
 }
 
-fn confirm() -> bool {
-    println!("Are you sure? [y/N]");
+fn confirm(out: &Printer) -> bool {
+    if !config::get().confirm {
+        return true;
+    }
+    out.prompt("Are you sure? [y/N]");
     read_line().is_ok_and(|v| ["y", "yes"].contains(&v.to_lowercase().trim()))
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() -> io::Result<()> {
+fn main() -> ExitCode {
     env_logger::builder()
         .parse_default_env()
         .format_timestamp_micros()
         .init();
-    let cli = TodoCli::parse();
-    match cli.command {
-        Some(Command::List { status }) => {
-            let tasks = Tasks::load_default()?;
-            match status {
-                None => print_visible_tasks(tasks.iter()),
-                Some(str_status) => match str_status.parse::<Status>() {
-                    Ok(only_status) => print_only_status_tasks(tasks.iter(), &[only_status]),
-                    Err(_) => {
-                        log::debug!("Unknown status {str_status}");
-                        print_visible_tasks(tasks.iter());
-                    }
-                },
+    let mut cli = TodoCli::parse();
+    let config = match Config::load() {
+        Ok(config) => config,
+        // The broken setting can still be fixed with `todo config`
+        Err(err) if matches!(cli.command, Some(Command::Config { .. })) => {
+            eprintln!("Error: {err}");
+            Config::default()
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            return ExitCode::from(CliError::Todo(err).exit_code());
+        }
+    };
+    if cli.command.is_none() && !config.default_command.trim().is_empty() {
+        let args = env::args_os().chain(config.default_command.split_whitespace().map(Into::into));
+        cli = TodoCli::parse_from(args);
+    }
+    config::init(config);
+    let out = Printer::new(cli.format);
+    let pick = Pick::new(&cli);
+    let scope = Scope::new(&cli);
+    match run(cli.command, &out, pick, &scope) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::NotFound) if out.is_table() => {
+            println!("Not found");
+            ExitCode::from(CliError::NotFound.exit_code())
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn run(command: Option<Command>, out: &Printer, pick: Pick, scope: &Scope) -> Result<(), CliError> {
+    match command {
+        Some(Command::List { filter, totals }) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            // `+work` is the same as `#work` which needs quotes in most shells
+            let (tags, status): (Vec<_>, Vec<_>) =
+                filter.iter().partition(|word| word.starts_with(['#', '+']));
+            let tags: Vec<_> = tags.iter().map(|tag| &tag[1..]).collect();
+            let only_status =
+                status
+                    .first()
+                    .and_then(|str_status| match str_status.parse::<Status>() {
+                        Ok(only_status) => Some(only_status),
+                        Err(_) => {
+                            log::debug!("Unknown status {str_status}");
+                            None
+                        }
+                    });
+            let tagged = tasks
+                .iter()
+                .filter(|task| tags.iter().all(|tag| task.has_tag(tag)));
+            match &only_status {
+                None => print_tasks(out, scope, &tasks, tagged, Status::VISIBLE, totals)?,
+                Some(only_status) => {
+                    let only_statuses = slice::from_ref(only_status);
+                    print_tasks(out, scope, &tasks, tagged, only_statuses, totals)?
+                }
             }
         }
-        Some(Command::Done { task }) => {
-            let task = task.join(" ");
-            let mut tasks = Tasks::load_default()?;
-            match tasks
-                .select_interactive(&task, false)
-                .and_then(|loc| tasks.set_done_idx(loc.idx))
-            {
-                None => print_not_found!(),
-                Some(t) => println!("Done: {t}"),
+        Some(Command::Done { task, force }) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let found = select(&tasks, &task.join(" "), false, out, pick)?;
+            let chosen: HashSet<_> = found.iter().map(|found| found.task.id).collect();
+            let unfinished: Vec<_> = found
+                .iter()
+                .flat_map(|found| tasks.subtasks(found.task.id))
+                .filter(|sub| sub.task.status == Status::Todo && !chosen.contains(&sub.task.id))
+                .collect();
+            if !force && !unfinished.is_empty() {
+                eprintln!("Subtasks to do:");
+                for sub in &unfinished {
+                    eprintln!("{}", sub.task);
+                }
+                return Err(CliError::Usage(
+                    "finish the subtasks first or use --force to mark the task done anyway",
+                ));
+            }
+            if !confirm_batch(out, pick, &found) {
+                return Ok(());
             }
+            let locs = locs(found);
+            let done: Vec<_> = locs
+                .into_iter()
+                .filter_map(|loc| tasks.set_done_idx(loc.idx).cloned())
+                .collect();
+            let next: Vec<_> = tasks
+                .take_spawned()
+                .into_iter()
+                .filter_map(|loc| tasks.find_idx(loc.idx).cloned())
+                .collect();
+            let unblocked: Vec<_> = tasks
+                .take_unblocked()
+                .into_iter()
+                .filter_map(|loc| tasks.find_idx(loc.idx).cloned())
+                .collect();
             tasks.save()?;
+            if out.is_table() {
+                print_changed(out, "done", "Done", &done)?;
+                print_changed(out, "created", "Next", &next)?;
+                print_changed(out, "unblocked", "Unblocked", &unblocked)?;
+            } else {
+                let records: Vec<_> = done
+                    .iter()
+                    .map(|task| out.task(task).action("done"))
+                    .chain(next.iter().map(|task| out.task(task).action("created")))
+                    .chain(
+                        unblocked
+                            .iter()
+                            .map(|task| out.task(task).action("unblocked")),
+                    )
+                    .collect();
+                out.write(&records)?;
+            }
         }
         Some(Command::Todo { task }) => {
-            let task = task.join(" ");
-            let mut tasks = Tasks::load_default()?;
-            match tasks
-                .select_interactive(&task, true)
-                .and_then(|loc| tasks.set_todo_idx(loc.idx))
-            {
-                None => print_not_found!(),
-                Some(t) => println!("TODO: {t}"),
-            }
-            tasks.save()?;
+            mutate(
+                out,
+                pick,
+                &task.join(" "),
+                true,
+                ("todo", "TODO"),
+                |tasks, idx| Ok(tasks.set_todo_idx(idx)),
+            )?;
         }
         Some(Command::Drop { task }) => {
-            enum Method {
-                Drop,
-                Remove,
-            }
-
-            let task = task.join(" ");
-            let mut tasks = Tasks::load_default()?;
-            match tasks
-                .select_interactive(&task, true)
-                .and_then(|loc| {
-                    tasks.find_idx(loc.idx).map(|task| match task.status {
-                        Status::Drop => (loc, Method::Remove),
-                        _ => (loc, Method::Drop),
-                    })
-                })
-                .and_then(|(loc, method)| match method {
-                    Method::Drop => tasks.set_dropped_idx(loc.idx).cloned().map(|x| (method, x)),
-                    Method::Remove => confirm()
-                        .then(|| tasks.remove(loc.idx))
-                        .flatten()
-                        .map(|x| (method, x)),
-                }) {
-                None => print_not_found!(),
-                Some((Method::Drop, t)) => println!("Dropped: {t}"),
-                Some((Method::Remove, t)) => println!("Removed: {t}"),
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let found = select(&tasks, &task.join(" "), true, out, pick)?;
+            if !confirm_batch(out, pick, &found) {
+                return Ok(());
             }
-
+            // Subtasks of a dropped task are dropped, dropped subtasks of a
+            // removed task are removed
+            let chosen: HashSet<_> = found.iter().map(|found| found.task.id).collect();
+            let mut subtasks: Vec<Found> = vec![];
+            for found in &found {
+                for sub in tasks.subtasks(found.task.id) {
+                    let removing = found.task.status == Status::Drop;
+                    if removing == (sub.task.status == Status::Drop)
+                        && !chosen.contains(&sub.task.id)
+                        && !subtasks.iter().any(|s| s.task.id == sub.task.id)
+                    {
+                        subtasks.push(sub);
+                    }
+                }
+            }
+            if !subtasks.is_empty() {
+                out.prompt(format_args!("{} subtasks go along:", subtasks.len()));
+                for sub in &subtasks {
+                    out.prompt(sub.task);
+                }
+            }
+            let (mut to_remove, to_drop): (Vec<_>, Vec<_>) = found
+                .into_iter()
+                .chain(subtasks.iter().copied())
+                .partition(|found| found.task.status == Status::Drop);
+            let ask = !subtasks.is_empty() || (!to_remove.is_empty() && pick != Pick::Query);
+            if ask && !confirm(out) {
+                return Ok(());
+            }
+            to_remove.sort_by_key(|found| found.loc.idx);
+            let (to_remove, to_drop) = (locs(to_remove), locs(to_drop));
+            let dropped: Vec<_> = to_drop
+                .into_iter()
+                .filter_map(|loc| tasks.set_dropped_idx(loc.idx).cloned())
+                .collect();
+            // Removing shifts the following tasks, go from the end
+            let mut removed: Vec<_> = to_remove
+                .into_iter()
+                .rev()
+                .filter_map(|loc| tasks.remove(loc.idx))
+                .collect();
+            removed.reverse();
             tasks.save()?;
+            if out.is_table() {
+                print_changed(out, "dropped", "Dropped", &dropped)?;
+                print_changed(out, "removed", "Removed", &removed)?;
+            } else {
+                let records: Vec<_> = dropped
+                    .iter()
+                    .map(|task| out.task(task).action("dropped"))
+                    .chain(removed.iter().map(|task| out.task(task).action("removed")))
+                    .collect();
+                out.write(&records)?;
+            }
         }
         Some(Command::Find { task }) => {
-            let tasks = Tasks::load_default()?;
-            let task = task.join(" ").to_lowercase();
-            let mut needle = task.as_str();
-            let mut filter = None;
-            if let Ok((tail, (attr, range))) = filter_parser::attr_and_range(&task) {
-                needle = tail.trim();
-                filter = Some((attr, range));
-            }
-            log::info!("filter is {filter:?}");
-            let matched = tasks
-                .find(needle, true, filter.is_some())
-                .into_iter()
-                .map(|(_, t)| t)
-                .filter(|t| match &filter {
-                    None => true,
-                    Some((attr, range)) => match attr {
-                        Attr::Updated => range.contains(&t.updated_at.date_naive()),
-                        Attr::Created => range.contains(&t.created_at.date_naive()),
-                    },
-                });
-
-            print_all_tasks(matched);
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let mut matched = tasks.query(&task.join(" "));
+            matched.retain(|found| scope.contains(found.task));
+            if out.is_table() {
+                print_tasks(
+                    out,
+                    scope,
+                    &tasks,
+                    matched.iter().map(|found| found.task),
+                    Status::ALL,
+                    false,
+                )?;
+            } else {
+                let records: Vec<_> = matched.iter().map(|found| out.found(found)).collect();
+                out.write(&records)?;
+            }
         }
         Some(Command::Detail { task }) => {
-            let task = task.join(" ");
-            let tasks = Tasks::load_default()?;
-
-            match tasks
-                .select_interactive(&task, true)
-                .and_then(|loc| tasks.find_idx(loc.idx))
-            {
-                None => print_not_found!(),
-                Some(task) => {
-                    let details = task.details().unwrap();
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let found = select(&tasks, &task.join(" "), true, out, pick)?;
+            if out.is_table() {
+                for found in &found {
+                    let details = details(&tasks, found.task).unwrap();
                     println!("{details}");
                 }
+            } else {
+                let records: Vec<_> = found.iter().map(|found| out.found(found)).collect();
+                out.write(&records)?;
             }
         }
-        Some(Command::Comment { task }) => {
-            let task = task.join(" ");
-            let mut tasks = Tasks::load_default()?;
-
-            match tasks
-                .select_interactive(&task, false)
-                .and_then(|loc| tasks.find_idx_mut(loc.idx))
-            {
-                None => print_not_found!(),
-                Some(task) => {
-                    println!("Comment for {task}:");
-                    let comment = read_multiline(task.comments.as_str())?;
-                    task.add_comment(comment);
+        Some(Command::LogOf { task }) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let found = select(&tasks, &task.join(" "), true, out, pick)?;
+            if out.is_table() {
+                for task in found.iter().map(|found| found.task) {
+                    println!("{task}");
+                    println!(
+                        "    {} created",
+                        task.created_at
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                    );
+                    if !task.history.is_empty() {
+                        println!("{}", task.history_log().unwrap());
+                    }
                 }
+            } else {
+                let records: Vec<_> = found
+                    .iter()
+                    .flat_map(|found| EventRecord::of(found.task))
+                    .collect();
+                out.write(&records)?;
             }
-
+        }
+        Some(Command::Comment { task, edit, delete }) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let found = select_one(&tasks, &task.join(" "), false, out, pick)?;
+            let (loc, task) = (found.loc, found.task.clone());
+            let entry = |n: usize| {
+                task.comments.get(n.wrapping_sub(1)).ok_or(CliError::Usage(
+                    "no comment with this number, see `todo detail`",
+                ))
+            };
+            let change = if let Some(n) = delete {
+                out.prompt(format_args!("Delete comment #{n} of {task}:"));
+                out.prompt(&entry(n)?.text);
+                if !confirm(out) {
+                    return Ok(());
+                }
+                CommentEdit::Delete(n)
+            } else {
+                let initial = edit.map(entry).transpose()?.map_or("", |c| c.text.as_str());
+                out.prompt(format_args!("Comment for {task}:"));
+                // The editor may stay open for minutes, don't block others
+                tasks.unlock();
+                let text = read_multiline(initial)?;
+                match edit {
+                    Some(_) if text.is_empty() => {
+                        return Err(CliError::Usage("use --delete to remove a comment"));
+                    }
+                    Some(n) => CommentEdit::Replace(n, text),
+                    None => CommentEdit::Add(text),
+                }
+            };
+            let task = tasks
+                .comment_idx(loc.idx, change)
+                .cloned()
+                .ok_or(CliError::NotFound)?;
             tasks.save()?;
+            if !out.is_table() {
+                print_changed(out, "commented", "Commented", &[task])?;
+            }
         }
         Some(Command::Rename { task }) => {
-            let task = task.join(" ");
-            let mut tasks = Tasks::load_default()?;
-            match tasks
-                .select_interactive(&task, false)
-                .and_then(|loc| tasks.find_idx_mut(loc.idx))
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let found = select_one(&tasks, &task.join(" "), false, out, pick)?;
+            let loc = found.loc;
+            out.prompt(format_args!("New name for {}:", found.task));
+            tasks.unlock();
+            let new_title = read_line()?;
+            let task = tasks
+                .change_title_idx(loc.idx, new_title)
+                .cloned()
+                .ok_or(CliError::NotFound)?;
+            tasks.save()?;
+            if !out.is_table() {
+                print_changed(out, "renamed", "Renamed", &[task])?;
+            }
+        }
+        Some(Command::Prio { mut task }) => {
+            let level = task
+                .pop()
+                .ok_or(CliError::Usage("specify the task and the priority level"))?;
+            let priority = match level.to_lowercase().as_str() {
+                "none" | "-" => None,
+                level => Some(level.parse::<Priority>().map_err(|()| {
+                    CliError::Usage("unknown priority, expected a, b, c, d or none")
+                })?),
+            };
+            let needle = task.join(" ");
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("prioritized", "Priority"),
+                |tasks, idx| Ok(tasks.set_priority_idx(idx, priority)),
+            )?;
+        }
+        Some(Command::Due { task }) => {
+            let (needle, due) = split_date_arg(task)?;
+            mutate(out, pick, &needle, false, ("due", "Due"), |tasks, idx| {
+                Ok(tasks.set_due_idx(idx, due))
+            })?;
+        }
+        Some(Command::Estimate { task }) => {
+            let (needle, estimate) = match task.last().map(String::as_str) {
+                Some("none" | "-") => (task[..task.len() - 1].join(" "), None),
+                _ => {
+                    let (needle, seconds) = split_duration_arg(&task)?;
+                    (needle, Some(seconds))
+                }
+            };
+            if needle.is_empty() {
+                return Err(CliError::Usage("specify the task before the time"));
+            }
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("estimated", "Estimate"),
+                |tasks, idx| Ok(tasks.set_estimate_idx(idx, estimate)),
+            )?;
+        }
+        Some(Command::Schedule { task }) => {
+            let (needle, scheduled) = split_date_arg(task)?;
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("scheduled", "Scheduled"),
+                |tasks, idx| Ok(tasks.set_scheduled_idx(idx, scheduled)),
+            )?;
+        }
+        Some(Command::Repeat { task }) => {
+            let words = task.join(" ");
+            let (needle, recurrence) = match words.rsplit_once(' ') {
+                Some((needle, "none" | "-")) => (needle, None),
+                _ => filter_parser::split_recurrence(&words)
+                    .map(|(needle, recurrence)| (needle, Some(recurrence)))
+                    .ok_or(CliError::Usage(
+                        "specify the task and the rule, e.g. `12 every friday` or `12 none`",
+                    ))?,
+            };
+            if needle.is_empty() {
+                return Err(CliError::Usage("specify the task before the rule"));
+            }
+            mutate(
+                out,
+                pick,
+                needle,
+                false,
+                ("repeated", "Repeats"),
+                |tasks, idx| Ok(tasks.set_recurrence_idx(idx, recurrence)),
+            )?;
+        }
+        Some(Command::Tag { mut task }) => {
+            let mut add = vec![];
+            let mut remove = vec![];
+            while let Some(word) =
+                task.pop_if(|word| word.len() > 1 && word.starts_with(['+', '-']))
             {
-                None => print_not_found!(),
-                Some(task) => {
-                    println!("New name for {task}:");
-                    let new_title = read_line()?;
-                    task.change_title(new_title);
+                match word.split_at(1) {
+                    ("+", tag) => add.push(tag.to_string()),
+                    (_, tag) => remove.push(tag.to_string()),
+                }
+            }
+            if add.is_empty() && remove.is_empty() {
+                return Err(CliError::Usage(
+                    "specify the task and the tags, e.g. `12 +work -home`",
+                ));
+            }
+            let needle = task.join(" ");
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("tagged", "Tagged"),
+                |tasks, idx| Ok(tasks.tag_idx(idx, &add, &remove)),
+            )?;
+        }
+        Some(Command::Block { task }) => {
+            let (needle, blocker) = split_on(&task);
+            let blocker = blocker.ok_or(CliError::Usage(
+                "specify the task and the one it waits for, e.g. `12 on 7`",
+            ))?;
+            let blockers: Vec<_> = {
+                let tasks = Tasks::load_default(LockMode::Shared)?;
+                let blockers = select(&tasks, &blocker, false, out, pick)?;
+                blockers.iter().map(|found| found.task.id).collect()
+            };
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("blocked", "Blocked"),
+                |tasks, idx| {
+                    for &blocker in &blockers {
+                        tasks.block_idx(idx, blocker)?;
+                    }
+                    Ok(tasks.find_idx(idx))
+                },
+            )?;
+        }
+        Some(Command::Unblock { task }) => {
+            let (needle, blocker) = split_on(&task);
+            let blockers: Vec<_> = match blocker {
+                Some(blocker) => {
+                    let tasks = Tasks::load_default(LockMode::Shared)?;
+                    select(&tasks, &blocker, true, out, pick)?
+                        .iter()
+                        .map(|found| Some(found.task.id))
+                        .collect()
                 }
+                None => vec![None],
+            };
+            mutate(
+                out,
+                pick,
+                &needle,
+                false,
+                ("unblocked", "Unblocked"),
+                |tasks, idx| {
+                    for &blocker in &blockers {
+                        tasks.unblock_idx(idx, blocker);
+                    }
+                    Ok(tasks.find_idx(idx))
+                },
+            )?;
+        }
+        Some(Command::Next) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let ready = tasks.iter().filter(|task| !tasks.is_blocked(task));
+            print_tasks(out, scope, &tasks, ready, &[Status::Todo], false)?;
+        }
+        Some(Command::Start { task }) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let loc = select_one(&tasks, &task.join(" "), false, out, pick)?.loc;
+            if let Some(timer) = tasks.timer()?
+                && timer.id == loc.id
+            {
+                let task = tasks.find_idx(loc.idx).unwrap();
+                let elapsed = duration_str(timer.elapsed(Utc::now()));
+                out.prompt(format_args!("Already started {elapsed} ago: {task}"));
+                return Ok(());
             }
+            let stopped = stop_timer(&mut tasks)?;
+            let started = tasks
+                .start_idx(loc.idx)
+                .cloned()
+                .ok_or(CliError::NotFound)?;
+            tasks.save()?;
+            print_stopped(out, &stopped)?;
+            print_changed(out, "started", "Started", &[started])?;
+        }
+        Some(Command::Stop) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let stopped = stop_timer(&mut tasks)?;
+            tasks.save()?;
+            if matches!(stopped, StoppedTimer::NotRunning) && out.is_table() {
+                println!("No timer is running");
+            }
+            print_stopped(out, &stopped)?;
+        }
+        Some(Command::Spent { task }) => {
+            let (needle, seconds) = split_duration_arg(&task)?;
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let loc = select_one(&tasks, &needle, false, out, pick)?.loc;
+            let entry = TimeEntry {
+                at: Utc::now(),
+                seconds,
+            };
+            let task = tasks
+                .add_time_idx(loc.idx, entry)
+                .cloned()
+                .ok_or(CliError::NotFound)?;
             tasks.save()?;
+            let header = format!("Spent {}", duration_str(seconds));
+            print_changed(out, "spent", &header, &[task])?;
+        }
+        Some(Command::Report {
+            command: ReportCommand::Time { range, by },
+        }) => {
+            let range = match range.join(" ").trim().to_lowercase().as_str() {
+                "" => NaiveDate::MIN..=NaiveDate::MAX,
+                words => match filter_parser::any_range(words) {
+                    Ok(("", range)) => range,
+                    _ => {
+                        return Err(CliError::Usage(
+                            "unknown date range, e.g. `last week` or `from 2025-05-01 to 2025-05-31`",
+                        ));
+                    }
+                },
+            };
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let shown = tasks.iter().filter(|task| scope.contains(task));
+            let (rows, total) = time_report(shown, &range, by);
+            if out.is_table() {
+                let width = rows.iter().map(|(group, _)| group.chars().count()).max();
+                let width = width.unwrap_or(0).max("Total".len());
+                for (group, spent) in &rows {
+                    println!("{group:<width$}  {}", duration_str(*spent));
+                }
+                println!("{:<width$}  {}", "Total", duration_str(total));
+            } else {
+                let records: Vec<_> = rows
+                    .into_iter()
+                    .map(|(group, spent)| TimeRecord { group, spent })
+                    .collect();
+                out.write(&records)?;
+            }
+        }
+        Some(Command::Tags) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let counts = tasks.tag_counts();
+            if out.is_table() {
+                for (tag, count) in &counts {
+                    println!("#{tag} {count}");
+                }
+            } else {
+                let records: Vec<_> = counts
+                    .iter()
+                    .map(|(tag, count)| TagRecord { tag, count: *count })
+                    .collect();
+                out.write(&records)?;
+            }
+        }
+        Some(Command::Move { task, to }) => {
+            let project = project_name(&to);
+            let header = format!("Moved to {}", project_str(project.as_deref()));
+            mutate(
+                out,
+                pick,
+                &task.join(" "),
+                true,
+                ("moved", &header),
+                |tasks, idx| Ok(tasks.set_project_idx(idx, project.clone())),
+            )?;
+        }
+        Some(Command::Projects) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let counts = tasks.project_counts();
+            if out.is_table() {
+                for (project, todo) in counts {
+                    let current = if project == scope.project.as_deref() {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{current} {} {todo}", project_str(project));
+                }
+            } else {
+                let records: Vec<_> = counts
+                    .into_iter()
+                    .map(|(project, todo)| ProjectRecord { project, todo })
+                    .collect();
+                out.write(&records)?;
+            }
         }
         Some(Command::RemoveDropped) => {
-            if confirm() {
-                let mut tasks = Tasks::load_default()?;
-                let removed_n = tasks.remove_dropped();
+            if confirm(out) {
+                let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+                let removed = tasks.remove_dropped();
                 tasks.save()?;
-                if removed_n > 0 {
-                    println!("{removed_n} dropped tasks were removed");
+                if !out.is_table() {
+                    print_changed(out, "removed", "Removed", &removed)?;
+                } else if !removed.is_empty() {
+                    println!("{} dropped tasks were removed", removed.len());
                 } else {
                     println!("Nothing to remove");
                 }
             }
         }
         Some(Command::Where) => {
-            if let Some(path) = Tasks::default_path().to_str() {
+            let (path, source) = Tasks::locate();
+            if !out.is_table() {
+                out.write(&[PathRecord {
+                    path: &path,
+                    source: source.kind(),
+                }])?;
+            } else if let Some(path) = path.to_str() {
                 println!("{path}");
+                // The reason goes to stderr so `$(todo where)` is just the path
+                eprintln!("{source}");
             }
         }
+        Some(Command::Init) => {
+            let path = Tasks::init(&env::current_dir()?)?;
+            if out.is_table() {
+                println!("Created {}", path.display());
+                println!("It's used in this directory and below instead of the home one");
+            } else {
+                out.write(&[PathRecord {
+                    path: &path,
+                    source: "local",
+                }])?;
+            }
+        }
+        Some(Command::Config { command }) => config_command(out, command)?,
         Some(Command::DropDone) => {
-            if confirm() {
-                let mut tasks = Tasks::load_default()?;
+            if confirm(out) {
+                let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
                 let dropped = tasks.drop_done();
-                if dropped > 0 {
-                    println!("{dropped} done tasks were dropped")
+                tasks.save()?;
+                if !out.is_table() {
+                    print_changed(out, "dropped", "Dropped", &dropped)?;
+                } else if !dropped.is_empty() {
+                    println!("{} done tasks were dropped", dropped.len())
                 } else {
                     println!("Nothing to drop")
                 }
-                tasks.save()?
             }
         }
-        Some(Command::External(task)) => add_task(task.join(" "), Status::Todo)?,
-        Some(Command::Log { task }) => add_task(task.join(" "), Status::Done)?,
+        Some(Command::Undo { n }) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let reverted = tasks.undo(n.unwrap_or(1))?;
+            tasks.save()?;
+            print_replayed(
+                out,
+                &tasks,
+                &reverted,
+                "undone",
+                "Undone",
+                "Nothing to undo",
+            )?;
+        }
+        Some(Command::Redo { n }) => {
+            let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+            let applied = tasks.redo(n.unwrap_or(1))?;
+            tasks.save()?;
+            print_replayed(out, &tasks, &applied, "redone", "Redone", "Nothing to redo")?;
+        }
+        Some(Command::History { n }) => {
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let journal = tasks.journal()?;
+            let entries = journal.entries().iter().rev().take(n.unwrap_or(10));
+            if out.is_table() {
+                for entry in entries {
+                    println!("{entry}");
+                }
+            } else {
+                let records: Vec<_> = entries.flat_map(HistoryRecord::of).collect();
+                out.write(&records)?;
+            }
+        }
+        Some(Command::Doctor { fix }) => {
+            let mode = if fix {
+                LockMode::Exclusive
+            } else {
+                LockMode::Shared
+            };
+            let mut tasks = Tasks::load_lenient(Tasks::default_path(), mode)?;
+            let issues = tasks.diagnose();
+            if !out.is_table() {
+                let records: Vec<_> = issues
+                    .iter()
+                    .map(|issue| IssueRecord::new(issue, fix))
+                    .collect();
+                if fix && !issues.is_empty() {
+                    tasks.repair(&issues)?;
+                    tasks.save()?;
+                }
+                out.write(&records)?;
+                return Ok(());
+            }
+            for issue in &issues {
+                println!("{issue}");
+            }
+            match (issues.len(), fix) {
+                (0, _) => println!("No problems found in {:?}", tasks.path()),
+                (n, false) => println!("{n} problems found, run `todo doctor --fix` to fix them"),
+                (_, true) => {
+                    let done = tasks.repair(&issues)?;
+                    tasks.save()?;
+                    println!("Fixed:");
+                    for fixed in done {
+                        println!("    {fixed}");
+                    }
+                }
+            }
+        }
+        Some(Command::Convert { to, output }) => {
+            let format: Format = to.to_lowercase().parse()?;
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            let output = output.unwrap_or_else(|| tasks.path().with_extension(format.extension()));
+            let converted = tasks.convert(&output, format)?;
+            if out.is_table() {
+                println!("{converted} tasks were written to {}", output.display());
+                println!("Set TASKS_FILE={} to use it", output.display());
+            } else {
+                out.write(&[ConvertRecord {
+                    path: &output,
+                    tasks: converted,
+                }])?;
+            }
+        }
+        Some(Command::External(task)) => add_task(out, scope, task.join(" "), Status::Todo, None)?,
+        Some(Command::Add { task, under }) => {
+            let parent = under.map(|needle| (needle, pick));
+            add_task(out, scope, task.join(" "), Status::Todo, parent)?
+        }
+        Some(Command::Log { task }) => add_task(out, scope, task.join(" "), Status::Done, None)?,
         None => {
-            let tasks = Tasks::load_default()?;
-            print_only_status_tasks(tasks.iter(), &[Status::Todo])
+            let tasks = Tasks::load_default(LockMode::Shared)?;
+            print_tasks(out, scope, &tasks, tasks.iter(), &[Status::Todo], false)?;
         }
     }
     Ok(())
 }
 
-fn add_task(title: String, status: Status) -> io::Result<()> {
-    let mut tasks = Tasks::load_default()?;
-    let loc = tasks.add(title, status);
+fn config_command(out: &Printer, command: ConfigCommand) -> Result<(), CliError> {
+    let paths = ConfigPaths::find();
+    let target = |local| match local {
+        true => paths.local_or_new(),
+        false => Ok(paths.global.clone()),
+    };
+    let (path, key, value) = match command {
+        ConfigCommand::List => {
+            let entries = config::get().entries();
+            if out.is_table() {
+                for path in [Some(&paths.global), paths.local.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    if path.exists() {
+                        println!("# {}", path.display());
+                    }
+                }
+                for (key, value) in entries {
+                    println!("{key} = {value}");
+                }
+            } else {
+                let records: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| ConfigRecord::new(key, value))
+                    .collect();
+                out.write(&records)?;
+            }
+            return Ok(());
+        }
+        ConfigCommand::Get { key } => {
+            let value = config::get()
+                .value(&key)
+                .ok_or(CliError::Usage("unknown setting, see `todo config list`"))?;
+            let record = ConfigRecord::new(&key, &value);
+            if out.is_table() {
+                println!("{}", record.value);
+            } else {
+                out.write(&[record])?;
+            }
+            return Ok(());
+        }
+        ConfigCommand::Set { key, value, local } => (target(local)?, key, Some(value)),
+        ConfigCommand::Unset { key, local } => (target(local)?, key, None),
+    };
+    let value = config::set(&path, &key, value.as_deref())?;
+    if out.is_table() {
+        match value {
+            Some(value) => println!("{key} = {value} in {}", path.display()),
+            None => println!("{key} is unset in {}", path.display()),
+        }
+    } else if let Some(value) = value.or_else(|| Config::default().value(&key)) {
+        out.write(&[ConfigRecord::new(&key, &value)])?;
+    }
+    Ok(())
+}
+
+/// Prints undone or redone operations. Machine-readable formats get the
+/// current state of every affected task.
+fn print_replayed(
+    out: &Printer,
+    tasks: &Tasks,
+    ops: &[Op],
+    action: &'static str,
+    header: &str,
+    nothing: &str,
+) -> io::Result<()> {
+    if !out.is_table() {
+        let mut seen = HashSet::new();
+        let records: Vec<_> = ops
+            .iter()
+            .filter(|op| seen.insert(op.id()))
+            .filter_map(|op| match op {
+                Op::Remove { task } => Some(task),
+                _ => tasks.iter().find(|t| t.id == op.id()),
+            })
+            .map(|task| out.task(task).action(action))
+            .collect();
+        return out.write(&records);
+    }
+    if ops.is_empty() {
+        println!("{nothing}");
+        return Ok(());
+    }
+    println!("{header}:");
+    for op in ops {
+        println!("    {op}");
+    }
+    Ok(())
+}
+
+/// Splits `12 on 7` into the task and the task after the last `on`
+fn split_on(words: &[String]) -> (String, Option<String>) {
+    match words.iter().rposition(|word| word == "on") {
+        Some(pos) => {
+            let blocker = words[pos + 1..].join(" ");
+            (
+                words[..pos].join(" "),
+                Some(blocker).filter(|b| !b.is_empty()),
+            )
+        }
+        None => (words.join(" "), None),
+    }
+}
+
+/// Splits `<task> <date>` arguments, the date is `None` for `none` or `-`
+fn split_date_arg(words: Vec<String>) -> Result<(String, Option<NaiveDate>), CliError> {
+    let words = words.join(" ");
+    let (needle, date) = match words.rsplit_once(' ') {
+        Some((needle, "none" | "-")) => (needle, None),
+        _ => filter_parser::split_date(&words)
+            .map(|(needle, date)| (needle, Some(date)))
+            .ok_or(CliError::Usage(
+                "specify the task and the date, e.g. `12 next friday` or `12 none`",
+            ))?,
+    };
+    if needle.is_empty() {
+        return Err(CliError::Usage("specify the task before the date"));
+    }
+    Ok((needle.to_string(), date))
+}
+
+/// Splits `<task> <time>` arguments: `12 1h 30m`
+fn split_duration_arg(words: &[String]) -> Result<(String, u64), CliError> {
+    (1..words.len())
+        .find_map(|pos| {
+            filter_parser::parse_duration(words[pos..].join(" ").trim_start_matches('~'))
+                .filter(|&seconds| seconds > 0)
+                .map(|seconds| (words[..pos].join(" "), seconds))
+        })
+        .ok_or(CliError::Usage(
+            "specify the task and the time, e.g. `12 1h30m`",
+        ))
+}
+
+/// Time spent on the `shown` tasks within `range` grouped by `by`, and the
+/// total. Days and weeks go in order, tags by name with untagged tasks last.
+fn time_report<'a>(
+    shown: impl Iterator<Item = &'a Task>,
+    range: &RangeInclusive<NaiveDate>,
+    by: GroupBy,
+) -> (Vec<(String, u64)>, u64) {
+    let config = config::get();
+    // The date orders days and weeks, it's the same for every tag
+    let mut groups: BTreeMap<(NaiveDate, String), u64> = BTreeMap::new();
+    let mut total = 0;
+    for task in shown {
+        for entry in &task.time {
+            let date = entry.at.with_timezone(&Local).date_naive();
+            if !range.contains(&date) {
+                continue;
+            }
+            total += entry.seconds;
+            let keys = match by {
+                GroupBy::Day => vec![(date, config.date(date).to_string())],
+                GroupBy::Week => {
+                    let week = date.iso_week();
+                    let monday =
+                        date - TimeDelta::days(date.weekday().num_days_from_monday().into());
+                    vec![(monday, format!("{}-W{:02}", week.year(), week.week()))]
+                }
+                GroupBy::Tag => match task.tags() {
+                    tags if tags.is_empty() => vec![(NaiveDate::MAX, "untagged".into())],
+                    tags => tags
+                        .into_iter()
+                        .map(|tag| (NaiveDate::MIN, format!("#{tag}")))
+                        .collect(),
+                },
+            };
+            for key in keys {
+                *groups.entry(key).or_default() += entry.seconds;
+            }
+        }
+    }
+    let rows = groups
+        .into_iter()
+        .map(|((_, group), spent)| (group, spent))
+        .collect();
+    (rows, total)
+}
+
+/// What [`Tasks::stop`] did, with a copy of the task: saving may move tasks
+/// around, so its index cannot be kept
+enum StoppedTimer {
+    NotRunning,
+    Tracked(Box<Task>, TimeEntry),
+    Lost { id: usize, seconds: u64 },
+}
+
+fn stop_timer(tasks: &mut Tasks) -> todo::Result<StoppedTimer> {
+    Ok(match tasks.stop()? {
+        Stopped::NotRunning => StoppedTimer::NotRunning,
+        Stopped::Tracked(loc, entry) => {
+            StoppedTimer::Tracked(Box::new(tasks.find_idx(loc.idx).unwrap().clone()), entry)
+        }
+        Stopped::Lost { id, seconds } => StoppedTimer::Lost { id, seconds },
+    })
+}
+
+/// Prints the task whose timer was stopped and the time added to it
+fn print_stopped(out: &Printer, stopped: &StoppedTimer) -> io::Result<()> {
+    match stopped {
+        StoppedTimer::Tracked(task, entry) if out.is_table() => {
+            println!("Stopped: {task}, {} spent", duration_str(entry.seconds));
+            Ok(())
+        }
+        StoppedTimer::Tracked(task, _) => out.write(&[out.task(task).action("stopped")]),
+        StoppedTimer::Lost { id, seconds } => {
+            out.prompt(format_args!(
+                "Stopped the timer of removed task {id}, {} spent is lost",
+                duration_str(*seconds)
+            ));
+            Ok(())
+        }
+        StoppedTimer::NotRunning => Ok(()),
+    }
+}
+
+/// Adds a task, `under` is the parent task to find and how to pick it
+fn add_task(
+    out: &Printer,
+    scope: &Scope,
+    title: String,
+    status: Status,
+    under: Option<(String, Pick)>,
+) -> Result<(), CliError> {
+    let (priority, title) = Priority::strip(&title);
+    let (planned, title) = filter_parser::planned(title);
+    let mut task = Task::new(title.into(), status);
+    task.priority = priority;
+    task.due = planned.due;
+    task.scheduled = planned.scheduled;
+    task.recurrence = planned.recurrence;
+    task.estimate = planned.estimate;
+    task.project = scope.project.clone();
+    let mut tasks = Tasks::load_default(LockMode::Exclusive)?;
+    if let Some((needle, pick)) = under {
+        let parent = select_one(&tasks, &needle, false, out, pick)?.task;
+        task.parent = Some(parent.id);
+        task.project = parent.project.clone();
+    }
+    let loc = tasks.add(task);
     tasks.save()?;
-    let task = tasks.find_idx(loc.idx).unwrap();
-    println!("Task has been created: {task}");
+    let task = tasks.find_idx(loc.idx).unwrap().clone();
+    print_changed(out, "created", "Task has been created", &[task])?;
     Ok(())
 }
 
@@ -828,7 +1762,120 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_translate() {
-        assert_eq!(translate("ghbdtn"), "привет")
+    fn test_select_pick() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path, LockMode::Exclusive).unwrap();
+        let cake = tasks.add(Task::new("bake a cake".into(), Status::Todo));
+        tasks.comment_idx(cake.idx, CommentEdit::Add("with milk".into()));
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.add(Task::new("bake bread".into(), Status::Todo));
+
+        let out = Printer::new(OutputFormat::Table);
+        let ids = |pick| {
+            select(&tasks, "milk", false, &out, pick)
+                .map(|found| found.iter().map(|f| f.loc.id).collect::<Vec<_>>())
+        };
+        assert_eq!(ids(Pick::All).unwrap(), [1, 2]);
+        // A title match is better than a comment match
+        assert_eq!(ids(Pick::First).unwrap(), [2]);
+        assert!(matches!(ids(Pick::Fail), Err(CliError::Ambiguous(2))));
+        assert!(matches!(
+            select_one(&tasks, "milk", false, &out, Pick::All),
+            Err(CliError::Usage(_))
+        ));
+
+        let ids = |needle| {
+            select(&tasks, needle, false, &out, Pick::Fail)
+                .map(|found| found.iter().map(|f| f.loc.id).collect::<Vec<_>>())
+        };
+        assert_eq!(ids("1,3").unwrap(), [1, 3]);
+        assert_eq!(ids("2-7").unwrap(), [2, 3]);
+        assert!(matches!(ids("5,6"), Err(CliError::NotFound)));
+    }
+
+    #[test]
+    fn test_time_report() {
+        let entry = |at: &str, seconds| TimeEntry {
+            at: format!("{at}T12:00:00Z").parse().unwrap(),
+            seconds,
+        };
+        let mut fix = Task::new("fix login #work #urgent".into(), Status::Todo);
+        fix.time = vec![entry("2025-04-25", 3600), entry("2025-04-28", 1800)];
+        let mut walk = Task::new("walk the dog".into(), Status::Done);
+        walk.time = vec![entry("2025-04-28", 900)];
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let tasks = [fix, walk];
+
+        let all = NaiveDate::MIN..=NaiveDate::MAX;
+        let (rows, total) = time_report(tasks.iter(), &all, GroupBy::Week);
+        assert_eq!(rows, [("2025-W17".into(), 3600), ("2025-W18".into(), 2700)]);
+        assert_eq!(total, 6300);
+
+        let range = date("2025-04-26")..=date("2025-05-04");
+        let (rows, total) = time_report(tasks.iter(), &range, GroupBy::Tag);
+        assert_eq!(
+            rows,
+            [
+                ("#urgent".into(), 1800),
+                ("#work".into(), 1800),
+                ("untagged".into(), 900)
+            ]
+        );
+        assert_eq!(total, 2700);
+    }
+
+    #[test]
+    fn test_totals() {
+        let task = |estimate, spent| {
+            let mut task = Task::new("write docs".into(), Status::Todo);
+            task.estimate = estimate;
+            task.time = vec![TimeEntry {
+                at: Utc::now(),
+                seconds: spent,
+            }];
+            task
+        };
+        let tasks = [
+            task(Some(3600), 1800),
+            task(Some(1800), 2700),
+            task(None, 600),
+        ];
+        let listed: Vec<_> = tasks.iter().collect();
+        assert_eq!(
+            Totals::of(&listed),
+            Totals {
+                estimate: 5400,
+                spent: 5100,
+                remaining: 1800
+            }
+        );
+    }
+
+    #[test]
+    fn test_tree() {
+        let task = |id, parent| Task {
+            id,
+            parent,
+            ..Task::new(format!("task {id}"), Status::Todo)
+        };
+        let tasks = [
+            task(1, None),
+            task(2, Some(1)),
+            task(3, Some(9)),
+            task(4, Some(2)),
+            task(5, Some(1)),
+            task(6, Some(7)),
+            task(7, Some(6)),
+        ];
+        let listed: Vec<_> = tasks.iter().collect();
+        let ordered: Vec<_> = tree(&listed)
+            .into_iter()
+            .map(|(depth, task)| (depth, task.id))
+            .collect();
+        assert_eq!(
+            ordered,
+            [(0, 1), (1, 2), (2, 4), (1, 5), (0, 3), (0, 6), (1, 7)]
+        );
     }
 }
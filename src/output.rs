@@ -0,0 +1,447 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::{fmt, io};
+use todo::doctor::Issue;
+use todo::journal::{Action, Entry, Op};
+use todo::search::Candidate;
+use todo::{
+    Change, Comment, Event, Found, Priority, Recurrence, Status, Task, TimeEntry, duration_str,
+    ids_str,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Table,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// CSV with a header, without task history
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn is_table(self) -> bool {
+        self == OutputFormat::Table
+    }
+}
+
+/// A task as printed by the machine-readable formats. Field names are stable.
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+    /// What the command did with the task, `null` for read-only commands
+    pub action: Option<&'static str>,
+    pub id: usize,
+    pub status: Status,
+    pub priority: Option<Priority>,
+    pub title: &'a str,
+    pub due: Option<NaiveDate>,
+    pub scheduled: Option<NaiveDate>,
+    pub recurrence: Option<Recurrence>,
+    /// `null` for the default project
+    pub project: Option<&'a str>,
+    /// ID of the parent task, `null` for top-level tasks
+    pub parent: Option<usize>,
+    /// IDs of the unfinished tasks to be done first, not included in CSV
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_by: Option<&'a [usize]>,
+    /// Expected time in seconds
+    pub estimate: Option<u64>,
+    /// Total time spent in seconds
+    pub spent: u64,
+    /// Time entries, not included in CSV
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<&'a [TimeEntry]>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Comment entries, not included in CSV
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comments: Option<&'a [Comment]>,
+    /// How the task matched the search query
+    pub matched: Option<Candidate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<&'a [Event]>,
+}
+
+impl Record<'_> {
+    pub fn action(mut self, action: &'static str) -> Self {
+        self.action = Some(action);
+        self
+    }
+}
+
+/// A line of `log-of`, the first one is the task creation
+#[derive(Debug, Serialize)]
+pub struct EventRecord {
+    pub id: usize,
+    pub at: DateTime<Utc>,
+    /// `created`, `status`, `renamed`, `commented`, `priority`, `due`,
+    /// `scheduled`, `recurrence`, `moved`, `parent`, `blocked_by`, `estimate`
+    /// or `spent`
+    pub event: &'static str,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl EventRecord {
+    pub fn of(task: &Task) -> Vec<Self> {
+        let created = Self {
+            id: task.id,
+            at: task.created_at,
+            event: "created",
+            from: None,
+            to: None,
+        };
+        let events = task.history.iter().map(|event| {
+            let (name, from, to) = match &event.change {
+                Change::Status { from, to } => (
+                    "status",
+                    Some(from.as_str().into()),
+                    Some(to.as_str().into()),
+                ),
+                Change::Renamed { from, to } => ("renamed", Some(from.clone()), Some(to.clone())),
+                Change::Commented => ("commented", None, None),
+                Change::Priority { from, to } => (
+                    "priority",
+                    from.map(|p| p.as_str().into()),
+                    to.map(|p| p.as_str().into()),
+                ),
+                Change::Due { from, to } => (
+                    "due",
+                    from.map(|d| d.to_string()),
+                    to.map(|d| d.to_string()),
+                ),
+                Change::Scheduled { from, to } => (
+                    "scheduled",
+                    from.map(|d| d.to_string()),
+                    to.map(|d| d.to_string()),
+                ),
+                Change::Recurrence { from, to } => {
+                    ("recurrence", from.map(String::from), to.map(String::from))
+                }
+                Change::Moved { from, to } => ("moved", from.clone(), to.clone()),
+                Change::BlockedBy { from, to } => (
+                    "blocked_by",
+                    (!from.is_empty()).then(|| ids_str(from)),
+                    (!to.is_empty()).then(|| ids_str(to)),
+                ),
+                Change::Parent { from, to } => (
+                    "parent",
+                    from.map(|id| id.to_string()),
+                    to.map(|id| id.to_string()),
+                ),
+                Change::Estimate { from, to } => {
+                    ("estimate", from.map(duration_str), to.map(duration_str))
+                }
+                Change::Spent { from, to } => {
+                    ("spent", Some(duration_str(*from)), Some(duration_str(*to)))
+                }
+            };
+            Self {
+                id: task.id,
+                at: event.at,
+                event: name,
+                from,
+                to,
+            }
+        });
+        std::iter::once(created).chain(events).collect()
+    }
+}
+
+/// A line of `history`: a single change of an applied entry or an undo/redo
+#[derive(Debug, Serialize)]
+pub struct HistoryRecord {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    /// `do`, `undo` or `redo`
+    pub kind: &'static str,
+    /// The entry undone or redone
+    pub target: Option<u64>,
+    pub id: Option<usize>,
+    pub change: Option<String>,
+}
+
+impl HistoryRecord {
+    pub fn of(entry: &Entry) -> Vec<Self> {
+        let record = |kind, target, op: Option<&Op>| Self {
+            seq: entry.seq,
+            at: entry.at,
+            kind,
+            target,
+            id: op.map(Op::id),
+            change: op.map(ToString::to_string),
+        };
+        match &entry.action {
+            Action::Do { ops } => ops.iter().map(|op| record("do", None, Some(op))).collect(),
+            Action::Undo { target } => vec![record("undo", Some(*target), None)],
+            Action::Redo { target } => vec![record("redo", Some(*target), None)],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssueRecord {
+    pub kind: &'static str,
+    pub id: Option<usize>,
+    /// Line of an unparseable task
+    pub line: Option<usize>,
+    pub message: String,
+    pub fixed: bool,
+}
+
+impl IssueRecord {
+    pub fn new(issue: &Issue, fixed: bool) -> Self {
+        Self {
+            kind: issue.kind(),
+            id: issue.id(),
+            line: match issue {
+                Issue::Unparseable(rejected) => Some(rejected.line),
+                _ => None,
+            },
+            message: issue.to_string(),
+            fixed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagRecord<'a> {
+    pub tag: &'a str,
+    /// Number of `todo` and `done` tasks with the tag
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectRecord<'a> {
+    /// `null` for the default project
+    pub project: Option<&'a str>,
+    /// Number of `todo` tasks
+    pub todo: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathRecord<'a> {
+    pub path: &'a Path,
+    /// Why the file is used: `env`, `config`, `local` or `home`. `config`
+    /// means `tasks_file` is set in the closest `.todo/config.toml` or, if it
+    /// doesn't set it, in the global `~/.config/todo/config.toml`
+    pub source: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfigRecord<'a> {
+    /// Nested settings are dotted: `search.similar_word`
+    pub key: &'a str,
+    /// Strings as is, other values in TOML: `0.85`, `true`, `["nvim", "vim"]`
+    pub value: String,
+}
+
+impl<'a> ConfigRecord<'a> {
+    pub fn new(key: &'a str, value: &toml::Value) -> Self {
+        let value = match value {
+            toml::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        Self { key, value }
+    }
+}
+
+/// A line of `report time`
+#[derive(Debug, Serialize)]
+pub struct TimeRecord {
+    /// The day, the week like `2025-W18` or the tag
+    pub group: String,
+    /// Time spent in seconds
+    pub spent: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConvertRecord<'a> {
+    pub path: &'a Path,
+    pub tasks: usize,
+}
+
+/// Failures distinguished by the exit code
+#[derive(Debug)]
+pub enum CliError {
+    NotFound,
+    /// Several tasks match, the number of them
+    Ambiguous(usize),
+    /// Arguments that cannot be used together
+    Usage(&'static str),
+    Todo(todo::Error),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Todo(_) => 1,
+            CliError::Usage(_) => 2,
+            CliError::NotFound => 3,
+            CliError::Ambiguous(_) => 4,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::NotFound => write!(f, "task not found"),
+            CliError::Ambiguous(n) => {
+                write!(f, "{n} tasks match, specify the ID or use --first or --all")
+            }
+            CliError::Usage(message) => write!(f, "{message}"),
+            CliError::Todo(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<todo::Error> for CliError {
+    fn from(err: todo::Error) -> Self {
+        CliError::Todo(err)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Todo(err.into())
+    }
+}
+
+pub struct Printer {
+    format: OutputFormat,
+}
+
+impl Printer {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format }
+    }
+
+    pub fn is_table(&self) -> bool {
+        self.format.is_table()
+    }
+
+    pub fn task<'a>(&self, task: &'a Task) -> Record<'a> {
+        Record {
+            action: None,
+            id: task.id,
+            status: task.status,
+            priority: task.priority,
+            title: &task.title,
+            due: task.due,
+            scheduled: task.scheduled,
+            recurrence: task.recurrence,
+            project: task.project.as_deref(),
+            parent: task.parent,
+            blocked_by: (self.format != OutputFormat::Csv).then_some(task.blocked_by.as_slice()),
+            estimate: task.estimate,
+            spent: task.spent(),
+            time: (self.format != OutputFormat::Csv).then_some(task.time.as_slice()),
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+            comments: (self.format != OutputFormat::Csv).then_some(task.comments.as_slice()),
+            matched: None,
+            history: (self.format != OutputFormat::Csv).then_some(task.history.as_slice()),
+        }
+    }
+
+    pub fn found<'a>(&self, found: &Found<'a>) -> Record<'a> {
+        Record {
+            matched: found.candidate,
+            ..self.task(found.task)
+        }
+    }
+
+    /// Prints rows in the machine-readable format. Nothing is printed for
+    /// [`OutputFormat::Table`], the caller prints text itself.
+    pub fn write<T: Serialize>(&self, rows: &[T]) -> io::Result<()> {
+        let mut out = stdout().lock();
+        match self.format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, rows)?;
+                writeln!(out)?;
+            }
+            OutputFormat::Jsonl => {
+                for row in rows {
+                    serde_json::to_writer(&mut out, row)?;
+                    writeln!(out)?;
+                }
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints a question so it doesn't mix with machine-readable output
+    pub fn prompt(&self, text: impl Display) {
+        if self.is_table() {
+            println!("{text}");
+        } else {
+            eprintln!("{text}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use todo::CommentEdit;
+
+    #[test]
+    fn test_record_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tasks = todo::Tasks::load(
+            dir.path().join("tasks.csv"),
+            todo::lock::LockMode::Exclusive,
+        )
+        .unwrap();
+        let loc = tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.set_done_idx(loc.idx);
+        let task = tasks
+            .comment_idx(loc.idx, CommentEdit::Add("oat".into()))
+            .unwrap();
+
+        let json = serde_json::to_value(Printer::new(OutputFormat::Json).task(task)).unwrap();
+        let keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(
+            keys,
+            [
+                "action",
+                "blocked_by",
+                "comments",
+                "created_at",
+                "due",
+                "estimate",
+                "history",
+                "id",
+                "matched",
+                "parent",
+                "priority",
+                "project",
+                "recurrence",
+                "scheduled",
+                "spent",
+                "status",
+                "time",
+                "title",
+                "updated_at"
+            ]
+        );
+        let events: Vec<_> = EventRecord::of(task).iter().map(|e| e.event).collect();
+        assert_eq!(events, ["created", "status", "commented"]);
+    }
+}
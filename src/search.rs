@@ -0,0 +1,182 @@
+use crate::config::{self, Locale};
+use crate::{Task, tag_of};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use strsim::jaro_winkler;
+
+static TRANSLIT_MAP: Lazy<HashMap<(Locale, char), char>> = Lazy::new(|| {
+    const ENG: &str = "qwertyuiop[]asdfghjkl;'zxcvbnm,./";
+    const RUS: &str = "йцукенгшщзхъфывапролджэячсмитьбю.";
+    const UKR: &str = "йцукенгшщзхїфівапролджєячсмитьбю.";
+
+    [(Locale::Ru, RUS), (Locale::Uk, UKR)]
+        .into_iter()
+        .flat_map(|(locale, layout)| {
+            ENG.chars()
+                .zip(layout.chars())
+                .map(move |(eng, c)| ((locale, eng), c))
+        })
+        .collect()
+});
+
+/// Converts text typed in the English keyboard layout to the `locale` one
+pub fn translate(input: &str) -> String {
+    translate_to(input, config::get().locale)
+}
+
+fn translate_to(input: &str, locale: Locale) -> String {
+    input
+        .chars()
+        .map(|c| TRANSLIT_MAP.get(&(locale, c)).copied().unwrap_or(c))
+        .collect()
+}
+
+pub trait StringExt {
+    fn contains_all<T: AsRef<str>>(&self, i: impl IntoIterator<Item = T>) -> bool;
+    fn not_empty(self) -> Option<Self>
+    where
+        Self: Sized;
+}
+
+impl<T> StringExt for T
+where
+    T: AsRef<str>,
+{
+    fn contains_all<Item: AsRef<str>>(&self, i: impl IntoIterator<Item = Item>) -> bool {
+        i.into_iter().all(|x| self.as_ref().contains(x.as_ref()))
+    }
+
+    fn not_empty(self) -> Option<Self> {
+        if self.as_ref().is_empty() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+fn is_similar_words(needles: &[&str], haystack: &[&str]) -> bool {
+    debug_assert!(needles.iter().all(|w| w.to_lowercase() == *w));
+    debug_assert!(haystack.iter().all(|w| w.to_lowercase() == *w));
+
+    let mut weights = Vec::with_capacity(needles.len() + haystack.len());
+    for needle_word in needles {
+        for haystack_word in haystack {
+            weights.push((
+                jaro_winkler(needle_word, haystack_word),
+                needle_word,
+                haystack_word,
+            ));
+        }
+    }
+    weights.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less));
+    weights.reverse();
+    let search = &config::get().search;
+    if let Some((sim, n, h)) = weights.iter().find(|(x, needle, title)| {
+        *x >= search.similar_word && (needle.len() >= 3 || title.len() >= 3)
+    }) {
+        log::debug!("found a similar word: {sim} ({n} x {h})");
+        return true;
+    }
+    let sum: f64 = weights.iter().take(needles.len()).map(|(x, _, _)| x).sum();
+    #[allow(clippy::cast_precision_loss)]
+    let count = (needles.len().saturating_sub(1) + 1) as f64;
+    let avg = sum / count;
+    if avg > search.similar_average {
+        log::debug!("average similarity is high enough: {avg}");
+        return true;
+    }
+    false
+}
+
+/// How a task matches a search needle, the best match first
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Candidate {
+    ById,
+    /// Has all `#tag`s of a needle made of tags only
+    ByTag,
+    SubsetOfTitle,
+    SimilarTitle,
+    SubsetOfComment,
+    SimilarComment,
+}
+
+impl Candidate {
+    pub fn check(needle: &str, task: &Task) -> Option<Self> {
+        debug_assert_eq!(needle, needle.trim().to_lowercase());
+        log::debug!("checking needle '{needle}' against task {task}");
+        if let Ok(id) = needle.parse::<usize>()
+            && task.id == id
+        {
+            return Some(Candidate::ById);
+        }
+
+        // Tags match exactly, the rest of the needle is searched as usual
+        let (tags, needle_words): (Vec<_>, Vec<_>) = needle
+            .split_whitespace()
+            .partition(|word| tag_of(word).is_some());
+        if !tags.iter().all(|tag| task.has_tag(tag)) {
+            return None;
+        }
+        if needle_words.is_empty() {
+            return Some(Candidate::ByTag);
+        }
+
+        let title = task.title.to_lowercase();
+        if title.contains_all(&needle_words) {
+            return Some(Candidate::SubsetOfTitle);
+        }
+
+        if is_similar_words(&needle_words, &title.split_whitespace().collect::<Vec<_>>()) {
+            return Some(Candidate::SimilarTitle);
+        }
+
+        if !task.comments.is_empty() {
+            let comment = task
+                .comments
+                .iter()
+                .map(|comment| comment.text.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if comment.contains_all(&needle_words) {
+                return Some(Candidate::SubsetOfComment);
+            }
+            if is_similar_words(
+                &needle_words,
+                &comment.split_whitespace().collect::<Vec<_>>(),
+            ) {
+                return Some(Candidate::SimilarComment);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate("ghbdtn"), "привет");
+        assert_eq!(translate_to("ghbdsn", Locale::Uk), "привіт");
+        assert_eq!(translate_to("ghbdtn", Locale::En), "ghbdtn");
+    }
+
+    #[test]
+    fn test_check_tags() {
+        let task = Task::new("fix login #work".into(), crate::Status::Todo);
+        assert_eq!(Candidate::check("#work", &task), Some(Candidate::ByTag));
+        assert_eq!(
+            Candidate::check("#work login", &task),
+            Some(Candidate::SubsetOfTitle)
+        );
+        // Tags are not matched fuzzily
+        assert_eq!(Candidate::check("#wrok", &task), None);
+        assert_eq!(Candidate::check("#home login", &task), None);
+    }
+}
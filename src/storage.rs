@@ -0,0 +1,403 @@
+mod csv;
+mod file;
+mod jsonl;
+mod sqlite;
+mod toml;
+
+pub use self::csv::Csv;
+pub use self::file::FileStorage;
+pub use self::jsonl::JsonLines;
+pub use self::sqlite::SqliteStorage;
+pub use self::toml::Toml;
+
+use crate::{Change, Comment, Error, Status, Task};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs, io};
+
+/// A snapshot of the stored tasks state used to detect external modifications.
+/// Modification time is not reliable enough: two writes may happen within the
+/// timer resolution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn of_content(content: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Self(hasher.finish())
+    }
+
+    pub fn of_revision(revision: u64) -> Self {
+        Self(revision)
+    }
+}
+
+/// Version of the stored data layout. Files without a version marker are
+/// considered version 1.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The first version with comments stored as a list of dated entries
+const COMMENT_LIST_VERSION: u32 = 3;
+
+/// `MIGRATIONS[n]` upgrades tasks of version `n + 1` to version `n + 2`.
+/// Codecs fill columns unknown to old versions with defaults, migrations fix
+/// what defaults cannot express.
+const MIGRATIONS: &[fn(&mut Task)] = &[
+    // 1 -> 2: the version marker and the `history` column were introduced,
+    // an empty history is the right default
+    |_| {},
+    // 2 -> 3: comments became a list of dated entries, a single legacy entry
+    // dates from the last comment edit
+    |task| {
+        let at = task
+            .history
+            .iter()
+            .rev()
+            .find(|event| event.change == Change::Commented)
+            .map_or(task.updated_at, |event| event.at);
+        for comment in &mut task.comments {
+            if comment.at == DateTime::UNIX_EPOCH {
+                comment.at = at;
+            }
+        }
+    },
+];
+
+/// Upgrades tasks loaded from a file of version `from` to [`FORMAT_VERSION`]
+pub fn migrate(tasks: &mut [Task], from: u32) {
+    let from = from.max(1) as usize;
+    for (n, migration) in MIGRATIONS.iter().enumerate().skip(from - 1) {
+        log::info!("migrating tasks from version {} to {}", n + 1, n + 2);
+        tasks.iter_mut().for_each(migration);
+    }
+}
+
+/// A stored task that cannot be parsed
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    /// Line number for text formats, ordinal number of the row otherwise
+    pub line: usize,
+    /// ID of the task if at least the ID is readable
+    pub id: Option<usize>,
+    /// The first field that has an invalid value, if it's known
+    pub field: Option<&'static str>,
+    /// The task as it's stored
+    pub raw: String,
+    pub error: String,
+}
+
+impl Rejected {
+    /// `fields` is the stored task as a JSON object, it's inspected to find out
+    /// the ID and the broken field
+    pub fn new(line: usize, raw: String, error: String, fields: &Value) -> Self {
+        Self {
+            line,
+            id: raw_id(fields.get("id")),
+            field: broken_field(fields),
+            raw,
+            error,
+        }
+    }
+}
+
+fn raw_id(value: Option<&Value>) -> Option<usize> {
+    match value? {
+        Value::Number(n) => n.as_u64().map(|n| n as usize),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn broken_field(fields: &Value) -> Option<&'static str> {
+    let text = |key| match fields.get(key) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) if s.trim().is_empty() => None,
+        Some(Value::String(s)) => Some(s.trim().to_string()),
+        Some(other) => Some(other.to_string()),
+    };
+    if raw_id(fields.get("id")).is_none() {
+        return Some("id");
+    }
+    if text("status").is_some_and(|s| s.parse::<Status>().is_err()) {
+        return Some("status");
+    }
+    if let Some(key) = ["created_at", "updated_at"]
+        .into_iter()
+        .find(|&key| text(key).is_some_and(|s| s.parse::<DateTime<Utc>>().is_err()))
+    {
+        return Some(key);
+    }
+    ["history", "blocked_by", "time"]
+        .into_iter()
+        .find(|&key| text(key).is_some_and(|s| serde_json::from_str::<Vec<Value>>(&s).is_err()))
+}
+
+/// Tasks that cannot be parsed are moved from `tasks.csv` to
+/// `tasks.rejected.csv` next to it
+pub fn rejected_path(filename: &Path) -> PathBuf {
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    filename.with_file_name(format!("{stem}.rejected.csv"))
+}
+
+/// Appends the rejected tasks to the side file, returns its path
+pub fn quarantine(filename: &Path, rejected: &[Rejected]) -> io::Result<PathBuf> {
+    let path = rejected_path(filename);
+    let is_new = fs::metadata(&path).map_or(true, |m| m.len() == 0);
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut wtr = ::csv::Writer::from_writer(file);
+    if is_new {
+        wtr.write_record(["rejected_at", "line", "id", "field", "error", "raw"])?;
+    }
+    let now = Utc::now().to_rfc3339();
+    for r in rejected {
+        wtr.write_record([
+            now.as_str(),
+            &r.line.to_string(),
+            &r.id.map(|id| id.to_string()).unwrap_or_default(),
+            r.field.unwrap_or_default(),
+            &r.error,
+            &r.raw,
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(path)
+}
+
+/// IDs of the tasks in the side file, they are not given to new tasks so
+/// that the rejected ones can be restored by hand
+pub fn quarantined_ids(filename: &Path) -> io::Result<Vec<usize>> {
+    let path = rejected_path(filename);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut rdr = ::csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(&path)?;
+    let Some(column) = rdr.headers()?.iter().position(|h| h == "id") else {
+        return Ok(vec![]);
+    };
+    let mut ids = vec![];
+    for record in rdr.records() {
+        ids.extend(record?.get(column).and_then(|id| id.parse::<usize>().ok()));
+    }
+    Ok(ids)
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    /// Version of the stored data before migration
+    pub version: u32,
+    pub rejected: Vec<Rejected>,
+}
+
+impl LoadReport {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            rejected: vec![],
+        }
+    }
+}
+
+pub struct Snapshot {
+    pub tasks: Vec<Task>,
+    pub fingerprint: Fingerprint,
+    pub report: LoadReport,
+}
+
+/// The place where tasks live
+pub trait Storage {
+    /// Reads all the stored tasks. Tasks that cannot be parsed are skipped
+    /// and listed in the report.
+    fn load(&mut self) -> io::Result<Snapshot>;
+
+    /// State of the stored tasks right now, without reading them
+    fn fingerprint(&mut self) -> io::Result<Fingerprint>;
+
+    /// Stores `tasks` in [`FORMAT_VERSION`]. `changed` contains IDs of tasks
+    /// added, changed or removed since loading, a storage may write only them
+    /// instead of everything.
+    fn save(&mut self, tasks: &[Task], changed: &HashSet<usize>) -> io::Result<Fingerprint>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+    Toml,
+    Sqlite,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            "toml" => Ok(Self::Toml),
+            "sqlite" | "sqlite3" | "db" => Ok(Self::Sqlite),
+            _ => Err(Error::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+            Format::Toml => "toml",
+            Format::Sqlite => "db",
+        }
+    }
+
+    /// `TODO_STORAGE` if set, otherwise guessed by the file extension. CSV is
+    /// the default.
+    pub fn detect(path: &Path) -> Self {
+        if let Ok(value) = env::var("TODO_STORAGE")
+            && !value.trim().is_empty()
+        {
+            match value.trim().to_lowercase().parse() {
+                Ok(format) => return format,
+                Err(err) => log::error!("TODO_STORAGE: {err}"),
+            }
+        }
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.to_lowercase().parse().ok())
+            .unwrap_or(Self::Csv)
+    }
+}
+
+pub fn open(path: &Path) -> io::Result<Box<dyn Storage>> {
+    open_as(path, Format::detect(path))
+}
+
+pub fn open_as(path: &Path, format: Format) -> io::Result<Box<dyn Storage>> {
+    log::debug!("{path:?} is opened as {format:?}");
+    let path = path.to_path_buf();
+    Ok(match format {
+        Format::Csv => Box::new(FileStorage::<Csv>::new(path)),
+        Format::JsonLines => Box::new(FileStorage::<JsonLines>::new(path)),
+        Format::Toml => Box::new(FileStorage::<Toml>::new(path)),
+        Format::Sqlite => Box::new(SqliteStorage::open(&path)?),
+    })
+}
+
+/// A list in a single column: a JSON array, an empty list is an empty string
+fn list_to_json<T: Serialize>(list: &[T]) -> serde_json::Result<String> {
+    match list {
+        [] => Ok(String::new()),
+        list => serde_json::to_string(list),
+    }
+}
+
+fn list_from_json<T: DeserializeOwned>(list: &str) -> serde_json::Result<Vec<T>> {
+    match list.trim() {
+        "" => Ok(vec![]),
+        list => serde_json::from_str(list),
+    }
+}
+
+/// Comments in a single column of data of `version`: a JSON list, or plain
+/// text written before comments became a list. Plain text is never probed as
+/// JSON, a legacy comment like `[]` is kept as is.
+fn comments_from_column(comments: &str, version: u32) -> Result<Vec<Comment>, String> {
+    if version < COMMENT_LIST_VERSION {
+        return Ok(Comment::legacy(comments));
+    }
+    list_from_json(comments).map_err(|e| format!("comments: {e}"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{Change, Event};
+
+    pub fn task(id: usize, title: &str) -> Task {
+        Task {
+            id,
+            ..Task::new(title.into(), Status::Todo)
+        }
+    }
+
+    #[test]
+    fn test_migrate_comments() {
+        let mut legacy = task(1, "bake");
+        legacy.comments = comments_from_column("no sugar\nwith cream", 2).unwrap();
+        let commented_at = Utc::now() - chrono::TimeDelta::days(2);
+        legacy.history.push(Event {
+            at: commented_at,
+            change: Change::Commented,
+        });
+        let mut plain = task(2, "brew");
+        plain.comments = comments_from_column("hops", 2).unwrap();
+        assert!(comments_from_column("", 2).unwrap().is_empty());
+        // Legacy text that happens to be valid JSON is still text
+        assert_eq!(
+            comments_from_column("[]", 2).unwrap(),
+            Comment::legacy("[]")
+        );
+        assert_eq!(comments_from_column("[]", 3).unwrap(), []);
+        assert!(comments_from_column("\"x\"", 3).is_err());
+
+        let mut tasks = [legacy, plain];
+        migrate(&mut tasks, 2);
+        assert_eq!(tasks[0].comments[0].text, "no sugar\nwith cream");
+        assert_eq!(tasks[0].comments[0].at, commented_at);
+        assert_eq!(tasks[1].comments[0].at, tasks[1].updated_at);
+    }
+
+    /// Saves `tasks` in every format and loads them back
+    pub fn round_trip(tasks: &[Task]) -> Vec<(Format, Vec<Task>)> {
+        let dir = tempfile::tempdir().unwrap();
+        let all = tasks.iter().map(|t| t.id).collect();
+        [Format::Csv, Format::JsonLines, Format::Toml, Format::Sqlite]
+            .into_iter()
+            .map(|format| {
+                let path = dir.path().join("tasks").with_extension(format.extension());
+                assert_eq!(Format::detect(&path), format);
+                open(&path).unwrap().save(tasks, &all).unwrap();
+                (format, open(&path).unwrap().load().unwrap().tasks)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let mut commented = task(2, "buy beer");
+        commented.status = Status::Done;
+        commented.comments = vec![Comment::new("where to buy hops?\n\"quoted\", line".into())];
+        commented.history.push(Event {
+            at: Utc::now(),
+            change: Change::Status {
+                from: Status::Todo,
+                to: Status::Done,
+            },
+        });
+        let tasks = [task(1, "buy milk"), commented];
+
+        for (format, loaded) in round_trip(&tasks) {
+            assert_eq!(loaded.len(), 2, "{format:?}");
+            for (loaded, task) in loaded.iter().zip(&tasks) {
+                assert_eq!(loaded.id, task.id, "{format:?}");
+                assert_eq!(loaded.title, task.title, "{format:?}");
+                assert_eq!(loaded.status, task.status, "{format:?}");
+                assert_eq!(loaded.created_at, task.created_at, "{format:?}");
+                assert_eq!(loaded.updated_at, task.updated_at, "{format:?}");
+                assert_eq!(loaded.comments, task.comments, "{format:?}");
+                assert_eq!(loaded.history, task.history, "{format:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use super::file::Codec;
+use super::{
+    FORMAT_VERSION, LoadReport, Rejected, comments_from_column, list_from_json, list_to_json,
+};
+use crate::{Priority, Recurrence, Status, Task};
+use ::csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+
+/// A task as it's stored in a CSV row. CSV has no nested values, so the
+/// comments and the history are stored as JSON arrays in a single column. Every column but `id`
+/// may be missing, unknown columns are ignored.
+#[derive(Serialize, Deserialize)]
+struct CsvRecord {
+    id: usize,
+    #[serde(default)]
+    status: Option<Status>,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    comments: String,
+    #[serde(default)]
+    history: String,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    due: Option<NaiveDate>,
+    #[serde(default)]
+    scheduled: Option<NaiveDate>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    parent: Option<usize>,
+    /// JSON array like `history`
+    #[serde(default)]
+    blocked_by: String,
+    #[serde(default)]
+    estimate: Option<u64>,
+    /// JSON array like `history`
+    #[serde(default)]
+    time: String,
+}
+
+impl TryFrom<&Task> for CsvRecord {
+    type Error = serde_json::Error;
+
+    fn try_from(task: &Task) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: task.id,
+            status: Some(task.status),
+            title: task.title.clone(),
+            created_at: Some(task.created_at),
+            updated_at: Some(task.updated_at),
+            comments: list_to_json(&task.comments)?,
+            history: list_to_json(&task.history)?,
+            priority: task.priority,
+            due: task.due,
+            scheduled: task.scheduled,
+            recurrence: task.recurrence,
+            project: task.project.clone(),
+            parent: task.parent,
+            blocked_by: list_to_json(&task.blocked_by)?,
+            estimate: task.estimate,
+            time: list_to_json(&task.time)?,
+        })
+    }
+}
+
+impl CsvRecord {
+    /// `version` is the format version of the file the record comes from
+    fn into_task(self, version: u32) -> Result<Task, String> {
+        let created_at = self.created_at.or(self.updated_at).unwrap_or_else(Utc::now);
+        Ok(Task {
+            id: self.id,
+            status: self.status.unwrap_or_default(),
+            title: self.title,
+            created_at,
+            updated_at: self.updated_at.unwrap_or(created_at),
+            comments: comments_from_column(&self.comments, version)?,
+            history: list_from_json(&self.history).map_err(|e| format!("history: {e}"))?,
+            priority: self.priority,
+            due: self.due,
+            scheduled: self.scheduled,
+            recurrence: self.recurrence,
+            project: self.project.filter(|p| !p.is_empty()),
+            parent: self.parent,
+            blocked_by: list_from_json(&self.blocked_by).map_err(|e| format!("blocked_by: {e}"))?,
+            estimate: self.estimate,
+            time: list_from_json(&self.time).map_err(|e| format!("time: {e}"))?,
+        })
+    }
+}
+
+const VERSION_KEY: &str = "format_version";
+
+/// The version marker is a comment line `# format_version = N` before the
+/// header, [`FORMAT_VERSION`] in files written now
+fn parse_version(content: &[u8]) -> u32 {
+    let first_line = content.split(|&b| b == b'\n').next().unwrap_or_default();
+    String::from_utf8_lossy(first_line)
+        .trim()
+        .strip_prefix('#')
+        .and_then(|line| line.split_once('='))
+        .filter(|(key, _)| key.trim() == VERSION_KEY)
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+fn raw_line(record: &StringRecord) -> String {
+    let mut wtr = WriterBuilder::new().from_writer(vec![]);
+    let _ = wtr.write_record(record);
+    let raw = wtr.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&raw).trim_end().to_string()
+}
+
+pub struct Csv;
+
+impl Codec for Csv {
+    fn decode(content: &[u8]) -> io::Result<(Vec<Task>, LoadReport)> {
+        let mut report = LoadReport::new(parse_version(content));
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .comment(Some(b'#'))
+            .flexible(true)
+            .from_reader(content);
+        let headers = rdr.headers()?.clone();
+        if !headers.is_empty() && !headers.iter().any(|h| h == "id") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the header has no `id` column",
+            ));
+        }
+        let mut tasks = vec![];
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |p| p.line() as usize);
+            let task = record
+                .deserialize::<CsvRecord>(Some(&headers))
+                .map_err(|e| e.to_string())
+                .and_then(|record| record.into_task(report.version));
+            match task {
+                Ok(task) => tasks.push(task),
+                Err(error) => {
+                    let fields = headers
+                        .iter()
+                        .zip(record.iter())
+                        .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                        .collect();
+                    let raw = raw_line(&record);
+                    let rejected = Rejected::new(line, raw, error, &Value::Object(fields));
+                    report.rejected.push(rejected);
+                }
+            }
+        }
+        Ok((tasks, report))
+    }
+
+    fn encode(tasks: &[Task]) -> io::Result<Vec<u8>> {
+        let mut buf = format!("# {VERSION_KEY} = {FORMAT_VERSION}\n").into_bytes();
+        let mut wtr = WriterBuilder::new().has_headers(true).from_writer(&mut buf);
+        for task in tasks {
+            wtr.serialize(CsvRecord::try_from(task)?)?;
+        }
+        wtr.flush()?;
+        drop(wtr);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tolerant_decode() {
+        let content = "\
+id,title,status,color
+1,buy milk,done,red
+2,buy beer,todoo,green
+3,bake bread,,
+";
+        let (tasks, report) = Csv::decode(content.as_bytes()).unwrap();
+        assert_eq!(report.version, 1);
+        let titles: Vec<_> = tasks.iter().map(|t| (t.id, t.status)).collect();
+        assert_eq!(titles, [(1, Status::Done), (3, Status::Todo)]);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].line, 3);
+        assert_eq!(report.rejected[0].raw, "2,buy beer,todoo,green");
+        assert_eq!(report.rejected[0].id, Some(2));
+        assert_eq!(report.rejected[0].field, Some("status"));
+
+        let content = "id,title,history,blocked_by\n1,deploy,,[2\n";
+        let (_, report) = Csv::decode(content.as_bytes()).unwrap();
+        assert_eq!(report.rejected[0].field, Some("blocked_by"));
+        assert!(report.rejected[0].error.starts_with("blocked_by: "));
+
+        let encoded = Csv::encode(&tasks).unwrap();
+        let (again, report) = Csv::decode(&encoded).unwrap();
+        assert_eq!(report.version, FORMAT_VERSION);
+        assert_eq!(again.len(), 2);
+    }
+
+    #[test]
+    fn test_legacy_comments() {
+        // Version 2 stored comments as plain text, even if it looks like JSON
+        let content = "# format_version = 2\nid,title,comments\n1,bake,[]\n2,brew,\"\"\"x\"\"\"\n";
+        let (tasks, report) = Csv::decode(content.as_bytes()).unwrap();
+        assert!(report.rejected.is_empty());
+        let texts: Vec<_> = tasks.iter().map(|t| t.comments[0].text.as_str()).collect();
+        assert_eq!(texts, ["[]", "\"x\""]);
+
+        // Since version 3 it's a JSON list, anything else is an error
+        let content = "# format_version = 3\nid,title,comments\n1,bake,[]\n2,brew,oops\n";
+        let (tasks, report) = Csv::decode(content.as_bytes()).unwrap();
+        assert!(tasks[0].comments.is_empty());
+        assert_eq!(report.rejected[0].id, Some(2));
+        assert!(report.rejected[0].error.starts_with("comments: "));
+    }
+}
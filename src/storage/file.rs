@@ -0,0 +1,176 @@
+use super::{Fingerprint, LoadReport, Snapshot, Storage};
+use crate::Task;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Converts tasks to the file content and back
+pub trait Codec {
+    /// Fails only if the file as a whole is broken, unparseable tasks are
+    /// reported instead, even if there's not a single good one
+    fn decode(content: &[u8]) -> io::Result<(Vec<Task>, LoadReport)>;
+    /// Always encodes in [`super::FORMAT_VERSION`]
+    fn encode(tasks: &[Task]) -> io::Result<Vec<u8>>;
+}
+
+/// Tasks in a single text file. The whole file is rewritten on every save.
+pub struct FileStorage<C> {
+    filename: PathBuf,
+    /// The main file was unreadable and tasks were recovered from the backup.
+    /// The broken file must not replace the good backup on the next save.
+    from_backup: bool,
+    codec: PhantomData<C>,
+}
+
+impl<C: Codec> FileStorage<C> {
+    pub fn new(filename: PathBuf) -> Self {
+        Self {
+            filename,
+            from_backup: false,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<C: Codec> Storage for FileStorage<C> {
+    fn load(&mut self) -> io::Result<Snapshot> {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .append(true)
+            .open(&self.filename)?;
+        let content = fs::read(&self.filename)?;
+        let fingerprint = Fingerprint::of_content(&content);
+        self.from_backup = false;
+        let (tasks, report) = match C::decode(&content) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                let backup = backup_path(&self.filename);
+                let Ok(content) = fs::read(&backup) else {
+                    return Err(err);
+                };
+                log::error!(
+                    "cannot read {:?} ({err}), falling back to {backup:?}",
+                    self.filename
+                );
+                self.from_backup = true;
+                C::decode(&content)?
+            }
+        };
+        Ok(Snapshot {
+            tasks,
+            fingerprint,
+            report,
+        })
+    }
+
+    fn fingerprint(&mut self) -> io::Result<Fingerprint> {
+        Ok(Fingerprint::of_content(&fs::read(&self.filename)?))
+    }
+
+    fn save(&mut self, tasks: &[Task], _changed: &HashSet<usize>) -> io::Result<Fingerprint> {
+        log::debug!("writing tasks to buffer before saving to file");
+        let buf = C::encode(tasks)?;
+        let dir = match self.filename.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        // Write everything to a temporary file in the same directory first so
+        // the final rename is atomic: the tasks file is either the old one or
+        // the new one, never a truncated mix of both.
+        let mut tmp = tempfile::Builder::new()
+            .prefix(".tasks")
+            .suffix(".tmp")
+            .tempfile_in(dir)?;
+        tmp.write_all(&buf)?;
+        tmp.as_file().sync_all()?;
+        if !self.from_backup && self.filename.exists() {
+            let backup = backup_path(&self.filename);
+            log::debug!("backing up {:?} to {backup:?}", self.filename);
+            fs::copy(&self.filename, &backup)?;
+        }
+        tmp.persist(&self.filename).map_err(|e| e.error)?;
+        sync_dir(dir)?;
+        self.from_backup = false;
+        Ok(Fingerprint::of_content(&buf))
+    }
+}
+
+/// The previous version of the tasks file is kept next to it with `.bak` suffix
+fn backup_path(filename: &Path) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push(".bak");
+    name.into()
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    // Persist the rename itself, not only the file contents
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Csv;
+    use crate::storage::tests::task;
+
+    #[test]
+    fn test_save_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut storage = FileStorage::<Csv>::new(path.clone());
+        let none = HashSet::new();
+        storage.save(&[task(1, "buy milk")], &none).unwrap();
+        storage
+            .save(&[task(1, "buy milk"), task(2, "buy beer")], &none)
+            .unwrap();
+
+        // Not even a tasks header
+        fs::write(&path, "garbage\n1,2,3\n").unwrap();
+        let snapshot = storage.load().unwrap();
+        let titles: Vec<_> = snapshot.tasks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["buy milk"]);
+        assert!(storage.from_backup);
+
+        storage.save(&snapshot.tasks, &none).unwrap();
+        let mut backup = FileStorage::<Csv>::new(backup_path(&path));
+        assert_eq!(backup.load().unwrap().tasks.len(), 1);
+        storage.load().unwrap();
+        assert!(!storage.from_backup);
+    }
+
+    #[test]
+    fn test_load_reports_rejected_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut storage = FileStorage::<Csv>::new(path.clone());
+        storage
+            .save(&[task(1, "buy milk")], &HashSet::new())
+            .unwrap();
+
+        // Every row is broken, but the file itself is readable
+        fs::write(
+            &path,
+            "id,status,title
+1,bogus,buy milk
+x,todo,buy beer
+",
+        )
+        .unwrap();
+        let snapshot = storage.load().unwrap();
+        assert!(snapshot.tasks.is_empty());
+        let ids: Vec<_> = snapshot.report.rejected.iter().map(|r| r.id).collect();
+        assert_eq!(ids, [Some(1), None]);
+        assert!(!storage.from_backup);
+    }
+}
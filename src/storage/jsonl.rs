@@ -0,0 +1,53 @@
+use super::file::Codec;
+use super::{FORMAT_VERSION, LoadReport, Rejected};
+use crate::Task;
+use serde_json::{Value, json};
+use std::io;
+
+const VERSION_KEY: &str = "format_version";
+
+/// One JSON object per task per line, so a change of a task is a change of a
+/// single line in `git diff`. The first line `{"format_version": N}` is the
+/// version marker, with [`FORMAT_VERSION`] in files written now.
+pub struct JsonLines;
+
+impl Codec for JsonLines {
+    fn decode(content: &[u8]) -> io::Result<(Vec<Task>, LoadReport)> {
+        let content = std::str::from_utf8(content).map_err(io::Error::other)?;
+        let mut report = LoadReport::new(1);
+        let mut tasks = vec![];
+        for (n, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = serde_json::from_str::<Value>(line);
+            if let Ok(Value::Object(obj)) = &value
+                && !obj.contains_key("id")
+                && let Some(version) = obj.get(VERSION_KEY).and_then(Value::as_u64)
+            {
+                report.version = version as u32;
+                continue;
+            }
+            let fields = value.as_ref().cloned().unwrap_or(Value::Null);
+            match value.and_then(serde_json::from_value) {
+                Ok(task) => tasks.push(task),
+                Err(err) => {
+                    let rejected = Rejected::new(n + 1, line.to_string(), err.to_string(), &fields);
+                    report.rejected.push(rejected);
+                }
+            }
+        }
+        Ok((tasks, report))
+    }
+
+    fn encode(tasks: &[Task]) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        serde_json::to_writer(&mut buf, &json!({ VERSION_KEY: FORMAT_VERSION }))?;
+        buf.push(b'\n');
+        for task in tasks {
+            serde_json::to_writer(&mut buf, task)?;
+            buf.push(b'\n');
+        }
+        Ok(buf)
+    }
+}
@@ -0,0 +1,304 @@
+use super::{
+    FORMAT_VERSION, Fingerprint, LoadReport, Rejected, Snapshot, Storage, comments_from_column,
+    list_from_json, list_to_json,
+};
+use crate::{Priority, Recurrence, Task};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tasks (
+    id INTEGER PRIMARY KEY,
+    status TEXT NOT NULL,
+    title TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    comments TEXT NOT NULL DEFAULT '',
+    history TEXT NOT NULL DEFAULT '',
+    priority TEXT,
+    due TEXT,
+    scheduled TEXT,
+    recurrence TEXT,
+    project TEXT,
+    parent INTEGER,
+    blocked_by TEXT NOT NULL DEFAULT '',
+    time TEXT NOT NULL DEFAULT '',
+    estimate INTEGER
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+";
+
+const COLUMNS: [&str; 16] = [
+    "id",
+    "status",
+    "title",
+    "created_at",
+    "updated_at",
+    "comments",
+    "history",
+    "priority",
+    "due",
+    "scheduled",
+    "recurrence",
+    "project",
+    "parent",
+    "blocked_by",
+    "time",
+    "estimate",
+];
+
+/// Columns added after the table was first created, with their definitions.
+/// `CREATE TABLE IF NOT EXISTS` leaves old tables as they are.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("priority", "TEXT"),
+    ("due", "TEXT"),
+    ("scheduled", "TEXT"),
+    ("recurrence", "TEXT"),
+    ("project", "TEXT"),
+    ("parent", "INTEGER"),
+    ("blocked_by", "TEXT NOT NULL DEFAULT ''"),
+    ("time", "TEXT NOT NULL DEFAULT ''"),
+    ("estimate", "INTEGER"),
+];
+
+/// Tasks in an embedded SQLite database. Only changed tasks are written on
+/// save.
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+fn to_io(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(to_io)?;
+        conn.execute_batch(SCHEMA).map_err(to_io)?;
+        Self::upgrade_schema(&conn).map_err(to_io)?;
+        Ok(Self { conn })
+    }
+
+    fn upgrade_schema(conn: &Connection) -> rusqlite::Result<()> {
+        let existing = conn
+            .prepare("SELECT name FROM pragma_table_info('tasks')")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<HashSet<_>>>()?;
+        for (column, definition) in ADDED_COLUMNS {
+            if !existing.contains(*column) {
+                log::info!("adding column {column} to the tasks table");
+                conn.execute_batch(&format!(
+                    "ALTER TABLE tasks ADD COLUMN {column} {definition}"
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every save increments the revision, so it changes whenever anyone
+    /// writes to the database
+    fn revision(conn: &Connection) -> rusqlite::Result<u64> {
+        conn.query_row("SELECT value FROM meta WHERE key = 'revision'", [], |row| {
+            row.get(0)
+        })
+        .optional()
+        .map(Option::unwrap_or_default)
+    }
+
+    /// Databases created before versioning have `user_version` 0
+    fn version(conn: &Connection) -> rusqlite::Result<u32> {
+        conn.query_row("PRAGMA user_version", [], |row| row.get::<_, u32>(0))
+            .map(|v| v.max(1))
+    }
+
+    fn read(conn: &Connection, version: u32) -> rusqlite::Result<(Vec<Task>, Vec<Rejected>)> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM tasks ORDER BY id",
+            COLUMNS.join(", ")
+        ))?;
+        let mut rows = stmt.query([])?;
+        let mut tasks = vec![];
+        let mut rejected = vec![];
+        let mut n = 0;
+        while let Some(row) = rows.next()? {
+            n += 1;
+            match Self::task(row, version) {
+                Ok(task) => tasks.push(task),
+                Err(error) => {
+                    let fields: serde_json::Map<_, _> = COLUMNS
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &column)| {
+                            let value = match row.get_ref(i) {
+                                Ok(ValueRef::Text(text)) => {
+                                    Value::String(String::from_utf8_lossy(text).to_string())
+                                }
+                                Ok(ValueRef::Integer(int)) => Value::from(int),
+                                _ => Value::Null,
+                            };
+                            (column.to_string(), value)
+                        })
+                        .collect();
+                    let raw = serde_json::to_string(&fields).unwrap_or_default();
+                    rejected.push(Rejected::new(n, raw, error, &Value::Object(fields)));
+                }
+            }
+        }
+        Ok((tasks, rejected))
+    }
+
+    fn task(row: &Row, version: u32) -> Result<Task, String> {
+        let status: String = row.get(1).map_err(|e| e.to_string())?;
+        let history: String = row.get(6).map_err(|e| e.to_string())?;
+        let priority: Option<String> = row.get(7).map_err(|e| e.to_string())?;
+        let recurrence: Option<String> = row.get(10).map_err(|e| e.to_string())?;
+        let blocked_by: String = row.get(13).map_err(|e| e.to_string())?;
+        let time: String = row.get(14).map_err(|e| e.to_string())?;
+        let comments: String = row.get(5).map_err(|e| e.to_string())?;
+        Ok(Task {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            status: status
+                .parse()
+                .map_err(|()| format!("unknown status {status:?}"))?,
+            title: row.get(2).map_err(|e| e.to_string())?,
+            created_at: row.get(3).map_err(|e| format!("created_at: {e}"))?,
+            updated_at: row.get(4).map_err(|e| format!("updated_at: {e}"))?,
+            comments: comments_from_column(&comments, version)?,
+            history: list_from_json(&history).map_err(|e| format!("history: {e}"))?,
+            priority: priority
+                .map(|p| p.parse().map_err(|()| format!("unknown priority {p:?}")))
+                .transpose()?,
+            due: row.get(8).map_err(|e| format!("due: {e}"))?,
+            scheduled: row.get(9).map_err(|e| format!("scheduled: {e}"))?,
+            recurrence: recurrence.map(Recurrence::try_from).transpose()?,
+            project: row.get(11).map_err(|e| e.to_string())?,
+            parent: row.get(12).map_err(|e| e.to_string())?,
+            blocked_by: list_from_json(&blocked_by).map_err(|e| format!("blocked_by: {e}"))?,
+            estimate: row.get(15).map_err(|e| format!("estimate: {e}"))?,
+            time: list_from_json(&time).map_err(|e| format!("time: {e}"))?,
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> io::Result<Snapshot> {
+        let tx = self.conn.transaction().map_err(to_io)?;
+        let version = Self::version(&tx).map_err(to_io)?;
+        let (tasks, rejected) = Self::read(&tx, version).map_err(to_io)?;
+        let fingerprint = Fingerprint::of_revision(Self::revision(&tx).map_err(to_io)?);
+        let report = LoadReport { version, rejected };
+        Ok(Snapshot {
+            tasks,
+            fingerprint,
+            report,
+        })
+    }
+
+    fn fingerprint(&mut self) -> io::Result<Fingerprint> {
+        Self::revision(&self.conn)
+            .map(Fingerprint::of_revision)
+            .map_err(to_io)
+    }
+
+    fn save(&mut self, tasks: &[Task], changed: &HashSet<usize>) -> io::Result<Fingerprint> {
+        let tx = self.conn.transaction().map_err(to_io)?;
+        {
+            let mut upsert = tx
+                .prepare(
+                    "INSERT OR REPLACE INTO tasks
+                     (id, status, title, created_at, updated_at, comments, history, priority,
+                      due, scheduled, recurrence, project, parent, blocked_by, time,
+                      estimate)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+                             ?16)",
+                )
+                .map_err(to_io)?;
+            let mut delete = tx
+                .prepare("DELETE FROM tasks WHERE id = ?1")
+                .map_err(to_io)?;
+            for &id in changed {
+                match tasks.iter().find(|t| t.id == id) {
+                    Some(task) => upsert.execute(params![
+                        task.id,
+                        task.status.as_str(),
+                        task.title,
+                        task.created_at,
+                        task.updated_at,
+                        list_to_json(&task.comments)?,
+                        list_to_json(&task.history)?,
+                        task.priority.map(Priority::as_str),
+                        task.due,
+                        task.scheduled,
+                        task.recurrence.map(String::from),
+                        task.project,
+                        task.parent,
+                        list_to_json(&task.blocked_by)?,
+                        list_to_json(&task.time)?,
+                        task.estimate,
+                    ]),
+                    None => delete.execute([id]),
+                }
+                .map_err(to_io)?;
+            }
+        }
+        let revision = Self::revision(&tx).map_err(to_io)? + 1;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('revision', ?1)",
+            [revision],
+        )
+        .map_err(to_io)?;
+        tx.pragma_update(None, "user_version", FORMAT_VERSION)
+            .map_err(to_io)?;
+        tx.commit().map_err(to_io)?;
+        log::debug!("{} tasks were written", changed.len());
+        Ok(Fingerprint::of_revision(revision))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::task;
+
+    #[test]
+    fn test_incremental_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.db");
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let tasks = [task(1, "buy milk"), task(2, "buy beer")];
+        let first = storage.save(&tasks, &HashSet::from([1, 2])).unwrap();
+
+        // Only the changed task is written, the rest is left untouched
+        let second = storage
+            .save(&[task(1, "buy oat milk")], &HashSet::from([1]))
+            .unwrap();
+        assert_ne!(first, second);
+        let mut other = SqliteStorage::open(&path).unwrap();
+        let snapshot = other.load().unwrap();
+        let titles: Vec<_> = snapshot.tasks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["buy oat milk", "buy beer"]);
+        assert_eq!(snapshot.fingerprint, second);
+
+        storage.save(&[], &HashSet::from([2])).unwrap();
+        assert_eq!(other.load().unwrap().tasks.len(), 1);
+
+        other
+            .conn
+            .execute("UPDATE tasks SET status = 'todoo'", [])
+            .unwrap();
+        let snapshot = storage.load().unwrap();
+        assert!(snapshot.tasks.is_empty());
+        assert_eq!(snapshot.report.version, FORMAT_VERSION);
+        assert_eq!(
+            snapshot.report.rejected[0].error,
+            "unknown status \"todoo\""
+        );
+    }
+}
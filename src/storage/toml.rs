@@ -0,0 +1,55 @@
+use super::file::Codec;
+use super::{FORMAT_VERSION, LoadReport, Rejected};
+use crate::Task;
+use ::toml::{Table, Value};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+#[derive(Serialize)]
+struct TomlFile<'a> {
+    format_version: u32,
+    #[serde(rename = "task")]
+    tasks: &'a [Task],
+}
+
+/// A `[[task]]` table per task, comment texts are multiline strings. Meant to be
+/// edited by hand.
+pub struct Toml;
+
+impl Codec for Toml {
+    fn decode(content: &[u8]) -> io::Result<(Vec<Task>, LoadReport)> {
+        let content = std::str::from_utf8(content).map_err(io::Error::other)?;
+        let mut file: Table = content.parse().map_err(io::Error::other)?;
+        let version = file
+            .get("format_version")
+            .and_then(Value::as_integer)
+            .map_or(1, |v| v as u32);
+        let mut report = LoadReport::new(version);
+        let mut tasks = vec![];
+        let values = match file.remove("task") {
+            Some(Value::Array(values)) => values,
+            _ => vec![],
+        };
+        for (n, value) in values.into_iter().enumerate() {
+            match Task::deserialize(value.clone()) {
+                Ok(task) => tasks.push(task),
+                Err(err) => {
+                    let raw = ::toml::to_string(&value).unwrap_or_default();
+                    let fields = serde_json::to_value(&value).unwrap_or_default();
+                    let rejected = Rejected::new(n + 1, raw, err.to_string(), &fields);
+                    report.rejected.push(rejected);
+                }
+            }
+        }
+        Ok((tasks, report))
+    }
+
+    fn encode(tasks: &[Task]) -> io::Result<Vec<u8>> {
+        let file = TomlFile {
+            format_version: FORMAT_VERSION,
+            tasks,
+        };
+        let content = ::toml::to_string_pretty(&file).map_err(io::Error::other)?;
+        Ok(content.into_bytes())
+    }
+}
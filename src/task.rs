@@ -0,0 +1,893 @@
+use crate::config;
+use crate::filter_parser::{self, TimeUnit};
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, TimeDelta, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A change of the comments entered by the user, entries are numbered from 1
+/// as shown by `todo detail`
+pub enum CommentEdit {
+    /// A new entry after the existing ones, empty text is ignored
+    Add(String),
+    /// New text for the entry, its date and author stay
+    Replace(usize, String),
+    /// Removes the entry
+    Delete(usize),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Hash, Eq, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Todo,
+    Done,
+    Drop,
+}
+
+impl Status {
+    pub const ALL: &'static [Self] = &[Self::Drop, Self::Done, Self::Todo];
+    pub const VISIBLE: &'static [Self] = &[Self::Done, Self::Todo];
+
+    pub fn is_visible(self) -> bool {
+        match self {
+            Status::Todo | Status::Done => true,
+            Status::Drop => false,
+        }
+    }
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Todo => "todo",
+            Status::Done => "done",
+            Status::Drop => "drop",
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "todo" => Ok(Status::Todo),
+            "done" => Ok(Status::Done),
+            "drop" => Ok(Status::Drop),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Importance of a task, `A` is the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::A => "A",
+            Priority::B => "B",
+            Priority::C => "C",
+            Priority::D => "D",
+        }
+    }
+
+    /// Splits a leading `!level` off a new task's title, e.g. `!high fix prod`
+    pub fn strip(title: &str) -> (Option<Self>, &str) {
+        let title = title.trim_start();
+        let (first, rest) = title.split_once(char::is_whitespace).unwrap_or((title, ""));
+        match first.strip_prefix('!').map(str::parse) {
+            Some(Ok(priority)) => (Some(priority), rest.trim_start()),
+            _ => (None, title),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "a" | "1" | "high" | "h" | "urgent" | "высокий" => Ok(Priority::A),
+            "b" | "2" | "medium" | "med" | "m" | "normal" | "средний" => Ok(Priority::B),
+            "c" | "3" | "low" | "l" | "низкий" => Ok(Priority::C),
+            "d" | "4" | "lowest" | "someday" => Ok(Priority::D),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `A`..`D` or `none`
+pub fn priority_str(priority: Option<Priority>) -> &'static str {
+    priority.map_or("none", Priority::as_str)
+}
+
+/// How often a task repeats. Stored as the text it's displayed as, e.g.
+/// `every 2nd of month`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Recurrence {
+    /// Every `n` days, weeks, months or years
+    Every(u32, TimeUnit),
+    Weekday(Weekday),
+    /// The given day of every month, the last day of shorter months
+    DayOfMonth(u32),
+}
+
+/// The given day of the month or the last day of the month if it's shorter
+fn day_of_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap_or_else(|| {
+        let first = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+        (first + Months::new(1)).pred_opt().unwrap()
+    })
+}
+
+impl Recurrence {
+    /// The first occurrence after `date`, `None` if it's out of the date range
+    pub fn next_after(self, date: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Recurrence::Every(n, unit) => unit.add(date, n),
+            Recurrence::Weekday(weekday) => {
+                let days = (7 + weekday.num_days_from_monday()
+                    - date.weekday().num_days_from_monday())
+                    % 7;
+                date.checked_add_signed(TimeDelta::days(if days == 0 { 7 } else { days.into() }))
+            }
+            Recurrence::DayOfMonth(day) => {
+                let this_month = day_of_month(date.year(), date.month(), day);
+                if this_month > date {
+                    Some(this_month)
+                } else {
+                    let next = date.checked_add_months(Months::new(1))?;
+                    Some(day_of_month(next.year(), next.month(), day))
+                }
+            }
+        }
+    }
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Every(n, unit) => {
+                let unit = match unit {
+                    TimeUnit::Days => "day",
+                    TimeUnit::Weeks => "week",
+                    TimeUnit::Months => "month",
+                    TimeUnit::Years => "year",
+                };
+                match n {
+                    1 => write!(f, "every {unit}"),
+                    n => write!(f, "every {n} {unit}s"),
+                }
+            }
+            Recurrence::Weekday(weekday) => {
+                let name = match weekday {
+                    Weekday::Mon => "monday",
+                    Weekday::Tue => "tuesday",
+                    Weekday::Wed => "wednesday",
+                    Weekday::Thu => "thursday",
+                    Weekday::Fri => "friday",
+                    Weekday::Sat => "saturday",
+                    Weekday::Sun => "sunday",
+                };
+                write!(f, "every {name}")
+            }
+            Recurrence::DayOfMonth(day) => {
+                let suffix = match (day % 10, day % 100) {
+                    (_, 11..=13) => "th",
+                    (1, _) => "st",
+                    (2, _) => "nd",
+                    (3, _) => "rd",
+                    _ => "th",
+                };
+                write!(f, "every {day}{suffix} of month")
+            }
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match filter_parser::recurrence(s.trim().to_lowercase().as_str()) {
+            Ok(("", recurrence)) => Ok(recurrence),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> Self {
+        recurrence.to_string()
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse().map_err(|()| format!("unknown recurrence {s:?}"))
+    }
+}
+
+/// The tag of a `#tag` word: letters, digits, `-`, `_` and `/` after `#`.
+/// Punctuation at the end is not a part of the tag: `#work,` is `work`.
+pub fn tag_of(word: &str) -> Option<&str> {
+    let tag = word
+        .strip_prefix('#')?
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() && !"-_/".contains(c));
+    let valid = |c: char| c.is_alphanumeric() || "-_/".contains(c);
+    (!tag.is_empty() && tag.chars().all(valid)).then_some(tag)
+}
+
+/// `every week` or `none`
+pub fn recurrence_str(recurrence: Option<Recurrence>) -> String {
+    recurrence.map_or_else(|| "none".into(), |recurrence| recurrence.to_string())
+}
+
+/// The parent task ID or `none`
+pub fn parent_str(parent: Option<usize>) -> String {
+    parent.map_or_else(|| "none".into(), |id| id.to_string())
+}
+
+/// IDs like `3, 5` or `none`
+pub fn ids_str(ids: &[usize]) -> String {
+    match ids {
+        [] => "none".into(),
+        ids => ids
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Time like `1h30m`, `45m` or `20s`, seconds are shown under a minute only
+pub fn duration_str(seconds: u64) -> String {
+    let (hours, minutes) = (seconds / 3600, seconds % 3600 / 60);
+    match (hours, minutes) {
+        (0, 0) if seconds > 0 => format!("{seconds}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, 0) => format!("{hours}h"),
+        (hours, minutes) => format!("{hours}h{minutes}m"),
+    }
+}
+
+/// Time like `~1h30m` or `none`
+pub fn estimate_str(estimate: Option<u64>) -> String {
+    estimate.map_or_else(
+        || "none".into(),
+        |seconds| format!("~{}", duration_str(seconds)),
+    )
+}
+
+/// Time spent on a task, tracked with `todo start` and `todo stop` or entered
+/// by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeEntry {
+    /// When the work started, when the entry was added for manual entries
+    pub at: DateTime<Utc>,
+    pub seconds: u64,
+}
+
+/// A single comment entry of a task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    pub at: DateTime<Utc>,
+    /// `$USER` of the one who wrote it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub text: String,
+}
+
+impl Comment {
+    /// A comment written now by the current user
+    pub fn new(text: String) -> Self {
+        Self {
+            at: Utc::now(),
+            author: std::env::var("USER").ok().filter(|user| !user.is_empty()),
+            text,
+        }
+    }
+
+    /// Comments stored as a single string before they became a list. The time
+    /// is unknown, the format migration fills it in from the history.
+    pub fn legacy(text: &str) -> Vec<Self> {
+        if text.trim().is_empty() {
+            return vec![];
+        }
+        vec![Self {
+            at: DateTime::UNIX_EPOCH,
+            author: None,
+            text: text.into(),
+        }]
+    }
+}
+
+/// Accepts both a list of comments and a legacy single string
+pub(crate) fn deserialize_comments<'de, D>(deserializer: D) -> Result<Vec<Comment>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Comments {
+        List(Vec<Comment>),
+        Legacy(String),
+    }
+
+    Ok(match Comments::deserialize(deserializer)? {
+        Comments::List(comments) => comments,
+        Comments::Legacy(text) => Comment::legacy(&text),
+    })
+}
+
+/// The project name or `none`
+pub fn project_str(project: Option<&str>) -> &str {
+    project.unwrap_or("none")
+}
+
+/// `2025-05-04` or `none`
+pub fn date_str(date: Option<NaiveDate>) -> String {
+    date.map_or_else(
+        || "none".into(),
+        |date| config::get().date(date).to_string(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Change {
+    Status {
+        from: Status,
+        to: Status,
+    },
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Commented,
+    Priority {
+        from: Option<Priority>,
+        to: Option<Priority>,
+    },
+    Due {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    Scheduled {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    Recurrence {
+        from: Option<Recurrence>,
+        to: Option<Recurrence>,
+    },
+    Moved {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    Parent {
+        from: Option<usize>,
+        to: Option<usize>,
+    },
+    BlockedBy {
+        from: Vec<usize>,
+        to: Vec<usize>,
+    },
+    Estimate {
+        from: Option<u64>,
+        to: Option<u64>,
+    },
+    /// Total time spent in seconds
+    Spent {
+        from: u64,
+        to: u64,
+    },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Status { from, to } => write!(f, "{from} -> {to}"),
+            Change::Renamed { from, to } => write!(f, "renamed {from:?} -> {to:?}"),
+            Change::Commented => write!(f, "comments changed"),
+            Change::Priority { from, to } => {
+                write!(
+                    f,
+                    "priority {} -> {}",
+                    priority_str(*from),
+                    priority_str(*to)
+                )
+            }
+            Change::Due { from, to } => write!(f, "due {} -> {}", date_str(*from), date_str(*to)),
+            Change::Scheduled { from, to } => {
+                write!(f, "scheduled {} -> {}", date_str(*from), date_str(*to))
+            }
+            Change::Recurrence { from, to } => write!(
+                f,
+                "repeat {} -> {}",
+                recurrence_str(*from),
+                recurrence_str(*to)
+            ),
+            Change::Moved { from, to } => write!(
+                f,
+                "moved {} -> {}",
+                project_str(from.as_deref()),
+                project_str(to.as_deref())
+            ),
+            Change::Parent { from, to } => {
+                write!(f, "parent {} -> {}", parent_str(*from), parent_str(*to))
+            }
+            Change::BlockedBy { from, to } => {
+                write!(f, "blocked by {} -> {}", ids_str(from), ids_str(to))
+            }
+            Change::Estimate { from, to } => {
+                write!(
+                    f,
+                    "estimate {} -> {}",
+                    estimate_str(*from),
+                    estimate_str(*to)
+                )
+            }
+            Change::Spent { from, to } => {
+                write!(f, "spent {} -> {}", duration_str(*from), duration_str(*to))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub at: chrono::DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let at = self.at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        write!(f, "{at} {}", self.change)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub id: usize,
+    #[serde(default)]
+    pub status: Status,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default = "Utc::now")]
+    pub created_at: chrono::DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: chrono::DateTime<Utc>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_comments",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub comments: Vec<Comment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// The deadline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<NaiveDate>,
+    /// When to start working on the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+    /// `None` is the default project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// ID of the task this one is a subtask of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    /// IDs of the tasks to be done before this one can start
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_by: Vec<usize>,
+    /// Expected time in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimate: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub time: Vec<TimeEntry>,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}. ", self.id)?;
+        if let Some(priority) = self.priority {
+            write!(f, "({priority}) ")?;
+        }
+        write!(f, "{}", self.title)?;
+        if let Some(recurrence) = self.recurrence {
+            write!(f, " ({recurrence})")?;
+        }
+        if let Some(scheduled) = self.scheduled {
+            write!(f, " (scheduled {})", config::get().date(scheduled))?;
+        }
+        if let Some(due) = self.due {
+            write!(f, " (due {})", config::get().date(due))?;
+        }
+        if self.estimate.is_some() {
+            write!(f, " ({})", estimate_str(self.estimate))?;
+        }
+        if !self.blocked_by.is_empty() {
+            write!(f, " (blocked by {})", ids_str(&self.blocked_by))?;
+        }
+        if !self.comments.is_empty() {
+            write!(f, " [*]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Task {
+    /// A task to be added with [`crate::Tasks::add`], which assigns the ID
+    pub fn new(title: String, status: Status) -> Self {
+        let now = Utc::now();
+        Self {
+            id: 0,
+            status,
+            title,
+            created_at: now,
+            updated_at: now,
+            comments: vec![],
+            history: vec![],
+            priority: None,
+            due: None,
+            scheduled: None,
+            recurrence: None,
+            project: None,
+            parent: None,
+            blocked_by: vec![],
+            estimate: None,
+            time: vec![],
+        }
+    }
+
+    /// Total time spent in seconds
+    pub fn spent(&self) -> u64 {
+        self.time.iter().map(|entry| entry.seconds).sum()
+    }
+
+    /// The estimate minus the time spent, `None` without an estimate
+    pub fn remaining(&self) -> Option<u64> {
+        Some(self.estimate?.saturating_sub(self.spent()))
+    }
+
+    /// Due date of the next occurrence of a recurring task if it's done
+    /// `today`. Missed occurrences are skipped.
+    pub fn next_occurrence(&self, today: NaiveDate) -> Option<NaiveDate> {
+        let recurrence = self.recurrence?;
+        let mut next = recurrence.next_after(self.due.unwrap_or(today))?;
+        while next < today {
+            next = recurrence.next_after(next)?;
+        }
+        Some(next)
+    }
+
+    /// Not finished after the due date
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.status == Status::Todo && self.due.is_some_and(|due| due < today)
+    }
+
+    /// `#tag` words of the title, lowercased and without `#`
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = vec![];
+        for tag in self.title.split_whitespace().filter_map(tag_of) {
+            let tag = tag.to_lowercase();
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags()
+            .contains(&tag.trim_start_matches('#').to_lowercase())
+    }
+
+    /// The title with `#tag` words appended for `add` and dropped for `remove`
+    pub fn retagged(&self, add: &[String], remove: &[String]) -> String {
+        let norm = |tag: &String| tag.trim_start_matches('#').to_lowercase();
+        let remove: Vec<_> = remove.iter().map(norm).collect();
+        let mut words: Vec<_> = self
+            .title
+            .split_whitespace()
+            .filter(|word| tag_of(word).is_none_or(|tag| !remove.contains(&tag.to_lowercase())))
+            .map(str::to_string)
+            .collect();
+        for tag in add.iter().map(norm) {
+            let word = format!("#{tag}");
+            if tag_of(&word).is_some() && !self.has_tag(&tag) && !words.contains(&word) {
+                words.push(word);
+            }
+        }
+        words.join(" ")
+    }
+
+    pub fn history_log(&self) -> Result<String, fmt::Error> {
+        use std::fmt::Write;
+
+        let mut buf = String::new();
+        for (n, event) in self.history.iter().enumerate() {
+            if n > 0 {
+                writeln!(buf)?;
+            }
+            write!(buf, "    {event}")?;
+        }
+        Ok(buf)
+    }
+
+    pub(crate) fn record(&mut self, change: Change) {
+        let now = Utc::now();
+        self.history.push(Event { at: now, change });
+        self.updated_at = now;
+    }
+
+    pub(crate) fn change_title(&mut self, new_title: String) {
+        if new_title != self.title {
+            let from = std::mem::replace(&mut self.title, new_title);
+            let to = self.title.clone();
+            self.record(Change::Renamed { from, to });
+        }
+        self.updated_at = Utc::now();
+    }
+
+    /// Numbers out of range are ignored
+    pub(crate) fn edit_comments(&mut self, edit: CommentEdit) {
+        let mut comments = self.comments.clone();
+        match edit {
+            CommentEdit::Add(text) => {
+                if !text.trim().is_empty() {
+                    comments.push(Comment::new(text));
+                }
+            }
+            CommentEdit::Replace(n, text) => {
+                if let Some(comment) = comments.get_mut(n.wrapping_sub(1)) {
+                    comment.text = text;
+                }
+            }
+            CommentEdit::Delete(n) => {
+                if (1..=comments.len()).contains(&n) {
+                    comments.remove(n - 1);
+                }
+            }
+        }
+        self.set_comments(comments);
+    }
+
+    pub(crate) fn set_comments(&mut self, comments: Vec<Comment>) {
+        if comments != self.comments {
+            self.comments = comments;
+            self.record(Change::Commented);
+        }
+    }
+
+    pub(crate) fn set_priority(&mut self, priority: Option<Priority>) {
+        if priority != self.priority {
+            let from = std::mem::replace(&mut self.priority, priority);
+            self.record(Change::Priority { from, to: priority });
+        }
+    }
+
+    pub(crate) fn set_due(&mut self, due: Option<NaiveDate>) {
+        if due != self.due {
+            let from = std::mem::replace(&mut self.due, due);
+            self.record(Change::Due { from, to: due });
+        }
+    }
+
+    pub(crate) fn set_scheduled(&mut self, scheduled: Option<NaiveDate>) {
+        if scheduled != self.scheduled {
+            let from = std::mem::replace(&mut self.scheduled, scheduled);
+            self.record(Change::Scheduled {
+                from,
+                to: scheduled,
+            });
+        }
+    }
+
+    pub(crate) fn set_recurrence(&mut self, recurrence: Option<Recurrence>) {
+        if recurrence != self.recurrence {
+            let from = std::mem::replace(&mut self.recurrence, recurrence);
+            self.record(Change::Recurrence {
+                from,
+                to: recurrence,
+            });
+        }
+    }
+
+    pub(crate) fn set_project(&mut self, project: Option<String>) {
+        if project != self.project {
+            let from = std::mem::replace(&mut self.project, project.clone());
+            self.record(Change::Moved { from, to: project });
+        }
+    }
+
+    pub(crate) fn set_parent(&mut self, parent: Option<usize>) {
+        if parent != self.parent {
+            let from = std::mem::replace(&mut self.parent, parent);
+            self.record(Change::Parent { from, to: parent });
+        }
+    }
+
+    pub(crate) fn set_blocked_by(&mut self, blocked_by: Vec<usize>) {
+        if blocked_by != self.blocked_by {
+            let from = std::mem::replace(&mut self.blocked_by, blocked_by.clone());
+            self.record(Change::BlockedBy {
+                from,
+                to: blocked_by,
+            });
+        }
+    }
+
+    pub(crate) fn set_estimate(&mut self, estimate: Option<u64>) {
+        if estimate != self.estimate {
+            let from = std::mem::replace(&mut self.estimate, estimate);
+            self.record(Change::Estimate { from, to: estimate });
+        }
+    }
+
+    pub(crate) fn set_time(&mut self, time: Vec<TimeEntry>) {
+        if time != self.time {
+            let from = self.spent();
+            self.time = time;
+            let to = self.spent();
+            self.record(Change::Spent { from, to });
+        }
+    }
+
+    pub(crate) fn set_status(&mut self, status: Status) {
+        if status != self.status {
+            let from = std::mem::replace(&mut self.status, status);
+            self.record(Change::Status { from, to: status });
+        }
+        self.updated_at = Utc::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::round_trip;
+
+    #[test]
+    fn test_edit_comments() {
+        let mut task = Task::new("bake".into(), Status::Todo);
+        task.edit_comments(CommentEdit::Add("  ".into()));
+        assert!(task.comments.is_empty() && task.history.is_empty());
+        task.edit_comments(CommentEdit::Add("no sugar".into()));
+        task.edit_comments(CommentEdit::Add("with *cream*".into()));
+        task.edit_comments(CommentEdit::Replace(1, "less sugar".into()));
+        task.edit_comments(CommentEdit::Delete(3));
+        let texts = |task: &Task| {
+            task.comments
+                .iter()
+                .map(|c| c.text.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texts(&task), ["less sugar", "with *cream*"]);
+        assert_eq!(task.history.len(), 3);
+        task.edit_comments(CommentEdit::Delete(1));
+        assert_eq!(texts(&task), ["with *cream*"]);
+
+        let legacy: Task =
+            serde_json::from_str(r#"{"id":1,"title":"bake","comments":"old\nnote"}"#).unwrap();
+        assert_eq!(legacy.comments, Comment::legacy("old\nnote"));
+        assert_eq!(legacy.comments[0].at, DateTime::UNIX_EPOCH);
+        let empty: Task = serde_json::from_str(r#"{"id":1,"comments":""}"#).unwrap();
+        assert!(empty.comments.is_empty());
+        let json = serde_json::to_string(&task).unwrap();
+        let loaded: Task = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.comments, task.comments);
+
+        task.comments.push(Comment {
+            author: None,
+            ..Comment::new("no author".into())
+        });
+        for (format, loaded) in round_trip(&[task.clone()]) {
+            assert_eq!(loaded[0].comments, task.comments, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_strip_priority() {
+        assert_eq!(
+            Priority::strip("!high fix prod"),
+            (Some(Priority::A), "fix prod")
+        );
+        assert_eq!(Priority::strip("!C  bake"), (Some(Priority::C), "bake"));
+        assert_eq!(Priority::strip("!wow fix prod"), (None, "!wow fix prod"));
+        assert_eq!(Priority::strip("fix !high prod"), (None, "fix !high prod"));
+    }
+
+    #[test]
+    fn test_priority_stored() {
+        let urgent = Task {
+            id: 1,
+            priority: Some(Priority::B),
+            ..Task::new("fix prod".into(), Status::Todo)
+        };
+        let plain = Task {
+            id: 2,
+            ..Task::new("bake".into(), Status::Todo)
+        };
+        for (format, loaded) in round_trip(&[urgent, plain]) {
+            let priorities: Vec<_> = loaded.iter().map(|t| t.priority).collect();
+            assert_eq!(priorities, [Some(Priority::B), None], "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_tags() {
+        let task = Task::new("fix login #Work #urgent, see #42".into(), Status::Todo);
+        assert_eq!(task.tags(), ["work", "urgent", "42"]);
+        assert!(task.has_tag("#work"));
+        assert!(!task.has_tag("home"));
+        assert_eq!(tag_of("#"), None);
+        assert_eq!(tag_of("C#"), None);
+        assert_eq!(
+            task.retagged(&["home".into(), "work".into()], &["urgent".into()]),
+            "fix login #Work see #42 #home"
+        );
+    }
+
+    #[test]
+    fn test_next_occurrence() {
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let mut task = Task::new("pay rent".into(), Status::Todo);
+        assert_eq!(task.next_occurrence(date("2025-05-04")), None);
+
+        task.recurrence = Some(Recurrence::DayOfMonth(31));
+        task.due = Some(date("2025-01-31"));
+        assert_eq!(
+            task.next_occurrence(date("2025-01-20")),
+            Some(date("2025-02-28"))
+        );
+        // Missed occurrences are skipped
+        assert_eq!(
+            task.next_occurrence(date("2025-05-04")),
+            Some(date("2025-05-31"))
+        );
+
+        task.recurrence = Some(Recurrence::Weekday(Weekday::Sun));
+        task.due = None;
+        assert_eq!(
+            task.next_occurrence(date("2025-05-04")),
+            Some(date("2025-05-11"))
+        );
+
+        task.recurrence = Some(Recurrence::DayOfMonth(2));
+        for (format, loaded) in round_trip(&[task.clone()]) {
+            assert_eq!(loaded[0].recurrence, task.recurrence, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,1582 @@
+use crate::config::{self, Configured, home_dir, project_dir};
+use crate::doctor::{self, Issue};
+use crate::filter_parser;
+use crate::journal::{self, Action, Journal, Op};
+use crate::lock::{self, FileLock, LockMode};
+use crate::search::{Candidate, translate};
+use crate::storage::{self, FORMAT_VERSION, Fingerprint, Format, LoadReport, Snapshot, Storage};
+use crate::timer::{self, Timer};
+use crate::{CommentEdit, Error, Priority, Recurrence, Result, Status, Task, TimeEntry};
+use chrono::{Local, NaiveDate, Utc};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Idx(usize);
+
+impl From<usize> for Idx {
+    fn from(value: usize) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Idx> for usize {
+    fn from(value: Idx) -> Self {
+        value.0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Loc {
+    pub idx: Idx,
+    pub id: usize,
+}
+
+impl Loc {
+    pub fn new<I: Into<Idx>>(idx: I, id: usize) -> Self {
+        Self {
+            idx: idx.into(),
+            id,
+        }
+    }
+}
+
+/// A task found by [`Tasks::find`]
+#[derive(Debug, Copy, Clone)]
+pub struct Found<'a> {
+    pub loc: Loc,
+    pub task: &'a Task,
+    /// How the task matched the needle, `None` when all tasks are listed
+    pub candidate: Option<Candidate>,
+}
+
+impl<'a> Found<'a> {
+    fn new(idx: usize, task: &'a Task, candidate: Option<Candidate>) -> Self {
+        Self {
+            loc: Loc::new(idx, task.id),
+            task,
+            candidate,
+        }
+    }
+}
+
+/// Tasks loaded from the tasks file. Changes are kept in memory until
+/// [`Tasks::save`] writes them back together with a journal entry.
+pub struct Tasks {
+    inner: Vec<Task>,
+    filename: PathBuf,
+    storage: Box<dyn Storage>,
+    /// State of the stored tasks at the moment they were loaded (or last saved)
+    fingerprint: Fingerprint,
+    report: LoadReport,
+    /// IDs of tasks changed, added or removed since loading. On save only
+    /// these tasks are applied on top of a file modified by someone else.
+    touched: HashSet<usize>,
+    /// IDs of tasks created since loading. They may clash with IDs of tasks
+    /// created concurrently by someone else.
+    added: HashSet<usize>,
+    /// Changes made since the last save, written to the journal on save
+    ops: Vec<Op>,
+    /// Undo and redo actions made since the last save
+    replayed: Vec<Action>,
+    /// Next occurrences of recurring tasks marked done, see [`Tasks::take_spawned`]
+    spawned: Vec<Loc>,
+    /// The greatest ID in the side file of rejected tasks, see
+    /// [`storage::quarantined_ids`]
+    quarantined_max: usize,
+    /// Tasks whose last blocker was marked done, see [`Tasks::take_unblocked`]
+    unblocked: Vec<Loc>,
+    /// The timer to write on save, `Some(None)` stops the running one
+    timer: Option<Option<Timer>>,
+    lock: Option<FileLock>,
+}
+
+/// What [`Tasks::stop`] did
+#[derive(Debug, Clone, Copy)]
+pub enum Stopped {
+    NotRunning,
+    /// The entry was added to the task
+    Tracked(Loc, TimeEntry),
+    /// The task of the timer was removed, the tracked time is lost
+    Lost {
+        id: usize,
+        seconds: u64,
+    },
+}
+
+/// Directory with the tasks file, in the home directory or in a project
+pub const TODO_DIR: &str = ".todo";
+pub(crate) const TASKS_CSV: &str = "tasks.csv";
+
+/// Why [`Tasks::locate`] chose the tasks file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSource {
+    /// The `TASKS_FILE` environment variable is set
+    Env,
+    /// `tasks_file` is set in this config file
+    Config(PathBuf),
+    /// `.todo/tasks.csv` was found in this directory
+    Local(PathBuf),
+    /// Nothing was found, the home directory one is used
+    Home,
+}
+
+impl PathSource {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PathSource::Env => "env",
+            PathSource::Config(_) => "config",
+            PathSource::Local(_) => "local",
+            PathSource::Home => "home",
+        }
+    }
+}
+
+impl Display for PathSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSource::Env => write!(f, "TASKS_FILE is set"),
+            PathSource::Config(path) => write!(f, "tasks_file is set in {}", path.display()),
+            PathSource::Local(dir) => write!(f, "found {TODO_DIR} in {}", dir.display()),
+            PathSource::Home => write!(
+                f,
+                "no {TODO_DIR}/{TASKS_CSV} in the current directory or above, \
+                 using the home directory one (run `todo init` to create one here)"
+            ),
+        }
+    }
+}
+
+/// `TASKS_FILE` wins, then the project directory config, then `.todo/tasks.csv`
+/// of `project`, then the global config
+fn locate(
+    tasks_file: Option<String>,
+    configured: Option<&Configured>,
+    project: Option<&Path>,
+    home: &Path,
+) -> (PathBuf, PathSource) {
+    if let Some(value) = tasks_file.filter(|value| !value.trim().is_empty()) {
+        log::debug!("TASKS_FILE was found: {value:?}");
+        return (value.trim().into(), PathSource::Env);
+    }
+    let by_config = |c: &Configured| (c.path.clone(), PathSource::Config(c.config.clone()));
+    if let Some(configured) = configured.filter(|c| c.local) {
+        return by_config(configured);
+    }
+    if let Some(dir) = project {
+        let path = dir.join(TODO_DIR).join(TASKS_CSV);
+        log::debug!("found {path:?}");
+        return (path, PathSource::Local(dir.to_path_buf()));
+    }
+    if let Some(configured) = configured {
+        return by_config(configured);
+    }
+    (home.join(TODO_DIR).join(TASKS_CSV), PathSource::Home)
+}
+
+impl Tasks {
+    pub fn default_path() -> PathBuf {
+        Self::locate().0
+    }
+
+    /// The tasks file and why it was chosen: `TASKS_FILE`, then `tasks_file`
+    /// of the config or `.todo/tasks.csv` in the current directory or the
+    /// closest parent, then `~/.todo/tasks.csv`
+    pub fn locate() -> (PathBuf, PathSource) {
+        let home = home_dir();
+        let cwd = env::current_dir().ok();
+        let project = cwd
+            .as_deref()
+            .and_then(|cwd| project_dir(cwd, &home, TASKS_CSV));
+        let configured = config::get().configured();
+        locate(env::var("TASKS_FILE").ok(), configured, project, &home)
+    }
+
+    /// Creates `.todo/tasks.csv` in `dir`, it's used in `dir` and below
+    pub fn init(dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(TODO_DIR).join(TASKS_CSV);
+        if path.exists() {
+            return Err(Error::AlreadyExists(path));
+        }
+        Self::load(path.clone(), LockMode::Exclusive)?.save()?;
+        Ok(path)
+    }
+
+    pub fn load_default(mode: LockMode) -> Result<Self> {
+        Self::load(Self::default_path(), mode)
+    }
+
+    /// Loads tasks holding the lock of the given mode until `Tasks` is dropped
+    /// or [`Tasks::unlock`] is called.
+    /// Use [`LockMode::Exclusive`] when the tasks are going to be saved, it
+    /// fails if some stored tasks cannot be parsed.
+    pub fn load(filename: PathBuf, mode: LockMode) -> Result<Self> {
+        let tasks = Self::load_lenient(filename, mode)?;
+        if mode == LockMode::Exclusive && !tasks.report.rejected.is_empty() {
+            return Err(Self::rejected_error(&tasks.filename, &tasks.report));
+        }
+        Ok(tasks)
+    }
+
+    /// Like [`Tasks::load`], but tolerates unparseable tasks. They must be
+    /// removed with [`Tasks::repair`] before saving.
+    pub fn load_lenient(filename: PathBuf, mode: LockMode) -> Result<Self> {
+        log::info!("loading tasks from {filename:?}");
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = FileLock::acquire(&lock::lock_path(&filename), mode)?;
+        let mut storage = storage::open(&filename)?;
+        let Snapshot {
+            mut tasks,
+            fingerprint,
+            report,
+        } = storage.load()?;
+        for rejected in &report.rejected {
+            log::error!(
+                "{filename:?}:{}: cannot parse the task, skipped: {}\n    {}",
+                rejected.line,
+                rejected.error,
+                rejected.raw
+            );
+        }
+        let quarantined_max = storage::quarantined_ids(&filename)?
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        let mut touched = HashSet::new();
+        if report.version < FORMAT_VERSION {
+            storage::migrate(&mut tasks, report.version);
+            // Rewrite every task in the new format on the first save
+            touched.extend(tasks.iter().map(|t| t.id));
+        }
+        Ok(Self {
+            inner: tasks,
+            filename,
+            storage,
+            fingerprint,
+            report,
+            touched,
+            added: HashSet::new(),
+            ops: vec![],
+            replayed: vec![],
+            spawned: vec![],
+            quarantined_max,
+            unblocked: vec![],
+            timer: None,
+            lock: Some(lock),
+        })
+    }
+
+    /// Saving would lose tasks that cannot be parsed
+    fn rejected_error(filename: &Path, report: &LoadReport) -> Error {
+        Error::Rejected {
+            path: filename.to_path_buf(),
+            count: report.rejected.len(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.filename
+    }
+
+    pub fn diagnose(&self) -> Vec<Issue> {
+        doctor::diagnose(&self.inner, &self.report.rejected, Utc::now())
+    }
+
+    /// Fixes the issues found by [`Tasks::diagnose`], returns descriptions of
+    /// what was done. Unparseable tasks are moved to a side file, duplicate IDs
+    /// are replaced with new ones, timestamps are clamped.
+    pub fn repair(&mut self, issues: &[Issue]) -> Result<Vec<String>> {
+        let mut done = vec![];
+        let now = Utc::now();
+        let mut next_id = self.next_loc().id;
+        for issue in issues {
+            match issue {
+                Issue::Unparseable(_) => {}
+                Issue::DuplicateId { id, .. } => {
+                    let positions: Vec<_> = (0..self.inner.len())
+                        .filter(|&idx| self.inner[idx].id == *id)
+                        .collect();
+                    for &idx in &positions[1..] {
+                        self.inner[idx].id = next_id;
+                        done.push(format!("task {id} got new ID: {}", self.inner[idx]));
+                        self.touch(next_id);
+                        next_id += 1;
+                    }
+                    self.touch(*id);
+                }
+                Issue::UpdatedBeforeCreated { id } => {
+                    for task in self.inner.iter_mut().filter(|t| t.id == *id) {
+                        task.updated_at = task.created_at;
+                    }
+                    done.push(format!("task {id}: update time set to creation time"));
+                    self.touch(*id);
+                }
+                Issue::FromFuture { id } => {
+                    for task in self.inner.iter_mut().filter(|t| t.id == *id) {
+                        task.created_at = task.created_at.min(now);
+                        task.updated_at = task.updated_at.min(now);
+                    }
+                    done.push(format!("task {id}: timestamps set to now"));
+                    self.touch(*id);
+                }
+            }
+        }
+        let rejected = std::mem::take(&mut self.report.rejected);
+        if !rejected.is_empty() {
+            let path = storage::quarantine(&self.filename, &rejected)?;
+            let rejected_max = rejected.iter().filter_map(|r| r.id).max();
+            self.quarantined_max = self.quarantined_max.max(rejected_max.unwrap_or(0));
+            for id in rejected.iter().filter_map(|r| r.id) {
+                // Incremental storages must delete these tasks
+                if !self.inner.iter().any(|t| t.id == id) {
+                    self.touch(id);
+                }
+            }
+            done.push(format!(
+                "{} unparseable tasks were moved to {path:?}",
+                rejected.len()
+            ));
+        }
+        Ok(done)
+    }
+
+    /// Releases the lock, e.g. while waiting for the user's input. Other
+    /// processes may change the file meanwhile, [`Tasks::save`] merges such
+    /// changes.
+    pub fn unlock(&mut self) {
+        log::debug!("releasing the tasks file lock");
+        self.lock = None;
+    }
+
+    fn touch(&mut self, id: usize) {
+        self.touched.insert(id);
+    }
+
+    /// Remembers a change to be written to the journal on save
+    fn record(&mut self, op: Op) {
+        self.touch(op.id());
+        self.ops.push(op);
+    }
+
+    /// Changes a field of a task with `set` and journals `op(id, before,
+    /// after)` of the values read with `get`, unless the value stays the same
+    fn set_field<T: PartialEq>(
+        &mut self,
+        idx: Idx,
+        get: impl Fn(&Task) -> T,
+        set: impl FnOnce(&mut Task),
+        op: impl FnOnce(usize, T, T) -> Op,
+    ) -> Option<&Task> {
+        let task = self.find_idx_mut(idx)?;
+        let before = get(task);
+        set(task);
+        let (id, after) = (task.id, get(task));
+        if before != after {
+            self.record(op(id, before, after));
+        }
+        self.find_idx(idx)
+    }
+
+    pub fn set_status_idx(&mut self, idx: Idx, status: Status) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.status,
+            |task| task.set_status(status),
+            |id, before, after| Op::Status { id, before, after },
+        )
+    }
+
+    pub fn change_title_idx(&mut self, idx: Idx, new_title: String) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.title.clone(),
+            |task| task.change_title(new_title),
+            |id, before, after| Op::Title { id, before, after },
+        )
+    }
+
+    /// Adds and removes `#tag` words of the title
+    pub fn tag_idx(&mut self, idx: Idx, add: &[String], remove: &[String]) -> Option<&Task> {
+        let title = self.find_idx(idx)?.retagged(add, remove);
+        if title == self.find_idx(idx)?.title {
+            return self.find_idx(idx);
+        }
+        self.change_title_idx(idx, title)
+    }
+
+    /// Tags of `todo` and `done` tasks with the number of tasks, the most
+    /// used first
+    pub fn tag_counts(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> = vec![];
+        for tag in self
+            .iter()
+            .filter(|task| task.status.is_visible())
+            .flat_map(Task::tags)
+        {
+            match counts.iter_mut().find(|(t, _)| *t == tag) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((tag, 1)),
+            }
+        }
+        counts.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+        counts
+    }
+
+    pub fn comment_idx(&mut self, idx: Idx, edit: CommentEdit) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.comments.clone(),
+            |task| task.edit_comments(edit),
+            |id, before, after| Op::Comments { id, before, after },
+        )
+    }
+
+    pub fn set_priority_idx(&mut self, idx: Idx, priority: Option<Priority>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.priority,
+            |task| task.set_priority(priority),
+            |id, before, after| Op::Priority { id, before, after },
+        )
+    }
+
+    pub fn set_due_idx(&mut self, idx: Idx, due: Option<NaiveDate>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.due,
+            |task| task.set_due(due),
+            |id, before, after| Op::Due { id, before, after },
+        )
+    }
+
+    pub fn set_scheduled_idx(&mut self, idx: Idx, scheduled: Option<NaiveDate>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.scheduled,
+            |task| task.set_scheduled(scheduled),
+            |id, before, after| Op::Scheduled { id, before, after },
+        )
+    }
+
+    pub fn set_recurrence_idx(
+        &mut self,
+        idx: Idx,
+        recurrence: Option<Recurrence>,
+    ) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.recurrence,
+            |task| task.set_recurrence(recurrence),
+            |id, before, after| Op::Recurrence { id, before, after },
+        )
+    }
+
+    /// Moves a task to another project, `None` is the default project
+    pub fn set_project_idx(&mut self, idx: Idx, project: Option<String>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.project.clone(),
+            |task| task.set_project(project),
+            |id, before, after| Op::Project { id, before, after },
+        )
+    }
+
+    /// Makes a task a subtask of the task with the `parent` ID, `None` makes
+    /// it a top-level task
+    pub fn set_parent_idx(&mut self, idx: Idx, parent: Option<usize>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.parent,
+            |task| task.set_parent(parent),
+            |id, before, after| Op::Parent { id, before, after },
+        )
+    }
+
+    /// Subtasks of the task with the given ID, their subtasks and so on, each
+    /// one after its parent
+    pub fn subtasks(&self, id: usize) -> Vec<Found<'_>> {
+        let mut found = vec![];
+        let mut parents = vec![id];
+        let mut seen = HashSet::from([id]);
+        while let Some(parent) = parents.pop() {
+            for (idx, task) in self.inner.iter().enumerate() {
+                // A hand-edited file may have cycles
+                if task.parent == Some(parent) && seen.insert(task.id) {
+                    parents.push(task.id);
+                    found.push(Found::new(idx, task, None));
+                }
+            }
+        }
+        found
+    }
+
+    pub fn set_blocked_by_idx(&mut self, idx: Idx, blocked_by: Vec<usize>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.blocked_by.clone(),
+            |task| task.set_blocked_by(blocked_by),
+            |id, before, after| Op::BlockedBy { id, before, after },
+        )
+    }
+
+    pub fn set_estimate_idx(&mut self, idx: Idx, estimate: Option<u64>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.estimate,
+            |task| task.set_estimate(estimate),
+            |id, before, after| Op::Estimate { id, before, after },
+        )
+    }
+
+    pub fn set_time_idx(&mut self, idx: Idx, time: Vec<TimeEntry>) -> Option<&Task> {
+        self.set_field(
+            idx,
+            |task| task.time.clone(),
+            |task| task.set_time(time),
+            |id, before, after| Op::Time { id, before, after },
+        )
+    }
+
+    pub fn add_time_idx(&mut self, idx: Idx, entry: TimeEntry) -> Option<&Task> {
+        let mut time = self.find_idx(idx)?.time.clone();
+        time.push(entry);
+        self.set_time_idx(idx, time)
+    }
+
+    /// The running timer, including the one started or stopped since loading
+    pub fn timer(&self) -> Result<Option<Timer>> {
+        match self.timer {
+            Some(timer) => Ok(timer),
+            None => Ok(Timer::load(&timer::timer_path(&self.filename))?),
+        }
+    }
+
+    /// Starts tracking time of a task. The running timer must be stopped with
+    /// [`Tasks::stop`] first, otherwise its time is lost.
+    pub fn start_idx(&mut self, idx: Idx) -> Option<&Task> {
+        let id = self.find_idx(idx)?.id;
+        self.timer = Some(Some(Timer::start(id)));
+        self.find_idx(idx)
+    }
+
+    /// Stops the running timer and adds the tracked time to its task
+    pub fn stop(&mut self) -> Result<Stopped> {
+        let Some(timer) = self.timer()? else {
+            return Ok(Stopped::NotRunning);
+        };
+        self.timer = Some(None);
+        let entry = TimeEntry {
+            at: timer.started_at,
+            seconds: timer.elapsed(Utc::now()),
+        };
+        let Some(idx) = self.inner.iter().position(|t| t.id == timer.id) else {
+            return Ok(Stopped::Lost {
+                id: timer.id,
+                seconds: entry.seconds,
+            });
+        };
+        self.add_time_idx(Idx(idx), entry);
+        Ok(Stopped::Tracked(Loc::new(idx, timer.id), entry))
+    }
+
+    /// Makes a task wait for the task with the `blocker` ID. Fails if the
+    /// blocker already waits for the task, directly or not.
+    pub fn block_idx(&mut self, idx: Idx, blocker: usize) -> Result<Option<&Task>> {
+        let Some(task) = self.find_idx(idx) else {
+            return Ok(None);
+        };
+        if let Some(path) = self.blocking_path(blocker, task.id) {
+            let mut cycle = vec![task.id];
+            cycle.extend(path);
+            return Err(Error::Cycle(cycle));
+        }
+        let mut blocked_by = task.blocked_by.clone();
+        if !blocked_by.contains(&blocker) {
+            blocked_by.push(blocker);
+        }
+        Ok(self.set_blocked_by_idx(idx, blocked_by))
+    }
+
+    /// Stops a task waiting for `blocker`, for every blocker if it's `None`
+    pub fn unblock_idx(&mut self, idx: Idx, blocker: Option<usize>) -> Option<&Task> {
+        let mut blocked_by = self.find_idx(idx)?.blocked_by.clone();
+        blocked_by.retain(|&id| blocker.is_some_and(|blocker| blocker != id));
+        self.set_blocked_by_idx(idx, blocked_by)
+    }
+
+    /// The IDs from `from` to `to` where each task waits for the next one
+    fn blocking_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let mut paths = vec![vec![from]];
+        let mut seen = HashSet::from([from]);
+        while let Some(path) = paths.pop() {
+            let last = *path.last().unwrap();
+            if last == to {
+                return Some(path);
+            }
+            for task in self.iter().filter(|task| task.id == last) {
+                for &next in &task.blocked_by {
+                    if seen.insert(next) {
+                        paths.push([path.as_slice(), &[next]].concat());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Waits for a task that isn't done yet
+    pub fn is_blocked(&self, task: &Task) -> bool {
+        task.blocked_by.iter().any(|&id| {
+            self.iter()
+                .any(|blocker| blocker.id == id && blocker.status != Status::Done)
+        })
+    }
+
+    /// `(done, total)` of the direct subtasks, dropped ones are not counted.
+    /// `None` if the task has no subtasks.
+    pub fn progress(&self, id: usize) -> Option<(usize, usize)> {
+        let subtasks: Vec<_> = self
+            .iter()
+            .filter(|task| task.parent == Some(id) && task.status.is_visible())
+            .collect();
+        let done = subtasks.iter().filter(|t| t.status == Status::Done).count();
+        (!subtasks.is_empty()).then_some((done, subtasks.len()))
+    }
+
+    /// Projects with the number of `todo` tasks, the default project is `None`
+    pub fn project_counts(&self) -> Vec<(Option<&str>, usize)> {
+        let mut counts: Vec<(Option<&str>, usize)> = vec![];
+        for task in self.iter() {
+            let project = task.project.as_deref();
+            let todo = usize::from(task.status == Status::Todo);
+            match counts.iter_mut().find(|(p, _)| *p == project) {
+                Some((_, count)) => *count += todo,
+                None => counts.push((project, todo)),
+            }
+        }
+        counts.sort();
+        counts
+    }
+
+    /// Marks a task done. The next occurrence of a recurring task is added as
+    /// a new task due on the next date of the rule. Tasks that only waited for
+    /// this one are reported by [`Tasks::take_unblocked`], they keep waiting
+    /// for it so reopening it blocks them again.
+    pub fn set_done_idx(&mut self, idx: Idx) -> Option<&Task> {
+        let was_done = self.find_idx(idx)?.status == Status::Done;
+        self.set_status_idx(idx, Status::Done)?;
+        if !was_done {
+            self.spawn_next(idx);
+            self.release(idx);
+        }
+        self.find_idx(idx)
+    }
+
+    fn release(&mut self, idx: Idx) {
+        let id = self.find_idx(idx).unwrap().id;
+        let waiting: Vec<_> = (0..self.inner.len())
+            .filter(|&i| self.inner[i].blocked_by.contains(&id))
+            .collect();
+        for i in waiting {
+            let task = &self.inner[i];
+            if task.status == Status::Todo && !self.is_blocked(task) {
+                self.unblocked.push(Loc::new(i, task.id));
+            }
+        }
+    }
+
+    /// Tasks that stopped waiting in [`Tasks::set_done_idx`] since the last
+    /// call
+    pub fn take_unblocked(&mut self) -> Vec<Loc> {
+        std::mem::take(&mut self.unblocked)
+    }
+
+    fn spawn_next(&mut self, idx: Idx) {
+        let done = self.find_idx(idx).unwrap();
+        let Some(due) = done.next_occurrence(Local::now().date_naive()) else {
+            return;
+        };
+        // Marking the task done again after reopening it must not repeat it twice
+        let spawned = self.iter().any(|task| {
+            task.status == Status::Todo
+                && task.title == done.title
+                && task.project == done.project
+                && task.due == Some(due)
+        });
+        if spawned {
+            return;
+        }
+        let mut next = Task::new(done.title.clone(), Status::Todo);
+        next.priority = done.priority;
+        next.recurrence = done.recurrence;
+        next.project = done.project.clone();
+        next.due = Some(due);
+        // Keep the gap between the start and the deadline
+        next.scheduled = done
+            .scheduled
+            .zip(done.due)
+            .map(|(scheduled, old_due)| due - (old_due - scheduled));
+        let loc = self.add(next);
+        self.spawned.push(loc);
+    }
+
+    /// Next occurrences added by [`Tasks::set_done_idx`] since the last call
+    pub fn take_spawned(&mut self) -> Vec<Loc> {
+        std::mem::take(&mut self.spawned)
+    }
+
+    pub fn set_todo_idx(&mut self, idx: Idx) -> Option<&Task> {
+        self.set_status_idx(idx, Status::Todo)
+    }
+
+    pub fn set_dropped_idx(&mut self, idx: Idx) -> Option<&Task> {
+        self.set_status_idx(idx, Status::Drop)
+    }
+
+    /// Returns the removed tasks
+    pub fn remove_dropped(&mut self) -> Vec<Task> {
+        let (dropped, kept): (Vec<_>, _) = std::mem::take(&mut self.inner)
+            .into_iter()
+            .partition(|t| !t.status.is_visible());
+        self.inner = kept;
+        for task in &dropped {
+            self.record(Op::Remove { task: task.clone() });
+        }
+        for task in &dropped {
+            self.detach(task.id);
+        }
+        dropped
+    }
+
+    /// Returns the dropped tasks
+    pub fn drop_done(&mut self) -> Vec<Task> {
+        let done: Vec<_> = (0..self.inner.len())
+            .filter(|&idx| self.inner[idx].status == Status::Done)
+            .collect();
+        done.into_iter()
+            .filter_map(|idx| self.set_dropped_idx(Idx(idx)).cloned())
+            .collect()
+    }
+
+    /// Subtasks of the removed task become top-level tasks and tasks waiting
+    /// for it stop waiting, so they aren't attached to a task that gets the ID
+    /// later
+    pub fn remove(&mut self, idx: Idx) -> Option<Task> {
+        let idx = idx.into();
+        if idx < self.inner.len() {
+            let task = self.inner.remove(idx);
+            self.record(Op::Remove { task: task.clone() });
+            self.detach(task.id);
+            Some(task)
+        } else {
+            None
+        }
+    }
+
+    /// Forgets a removed task in its subtasks and the tasks waiting for it
+    fn detach(&mut self, id: usize) {
+        for idx in 0..self.inner.len() {
+            if self.inner[idx].parent == Some(id) {
+                self.set_parent_idx(Idx(idx), None);
+            }
+            if self.inner[idx].blocked_by.contains(&id) {
+                self.unblock_idx(Idx(idx), Some(id));
+            }
+        }
+    }
+
+    /// Applies a journaled change without recording it. Returns `false` if the
+    /// change cannot be applied because the task doesn't exist (or already
+    /// exists for [`Op::Add`]).
+    fn apply(&mut self, op: &Op) -> bool {
+        let id = op.id();
+        let pos = self.inner.iter().position(|t| t.id == id);
+        match (op, pos) {
+            (Op::Add { task }, None) => self.inner.push(task.clone()),
+            (Op::Remove { .. }, Some(pos)) => {
+                self.inner.remove(pos);
+            }
+            (Op::Status { after, .. }, Some(pos)) => self.inner[pos].set_status(*after),
+            (Op::Title { after, .. }, Some(pos)) => self.inner[pos].change_title(after.clone()),
+            (Op::Priority { after, .. }, Some(pos)) => self.inner[pos].set_priority(*after),
+            (Op::Due { after, .. }, Some(pos)) => self.inner[pos].set_due(*after),
+            (Op::Scheduled { after, .. }, Some(pos)) => self.inner[pos].set_scheduled(*after),
+            (Op::Recurrence { after, .. }, Some(pos)) => self.inner[pos].set_recurrence(*after),
+            (Op::Project { after, .. }, Some(pos)) => self.inner[pos].set_project(after.clone()),
+            (Op::Parent { after, .. }, Some(pos)) => self.inner[pos].set_parent(*after),
+            (Op::BlockedBy { after, .. }, Some(pos)) => {
+                self.inner[pos].set_blocked_by(after.clone());
+            }
+            (Op::Estimate { after, .. }, Some(pos)) => self.inner[pos].set_estimate(*after),
+            (Op::Time { after, .. }, Some(pos)) => self.inner[pos].set_time(after.clone()),
+            (Op::Comments { after, .. }, Some(pos)) => {
+                self.inner[pos].set_comments(after.clone());
+            }
+            _ => return false,
+        }
+        self.touch(id);
+        true
+    }
+
+    /// Writes all tasks to a new file of the given format, the journal is
+    /// copied along. Returns the number of written tasks.
+    pub fn convert(&self, path: &Path, format: Format) -> Result<usize> {
+        if fs::metadata(path).is_ok_and(|m| m.len() > 0) {
+            return Err(Error::AlreadyExists(path.to_path_buf()));
+        }
+        let all = self.iter().map(|t| t.id).collect();
+        storage::open_as(path, format)?.save(&self.inner, &all)?;
+        let journal = journal::journal_path(&self.filename);
+        if journal.exists() {
+            fs::copy(journal, journal::journal_path(path))?;
+        }
+        Ok(self.inner.len())
+    }
+
+    pub fn journal(&self) -> Result<Journal> {
+        Ok(Journal::load(journal::journal_path(&self.filename))?)
+    }
+
+    /// Reverts up to `n` latest changes. Returns the reverted operations.
+    pub fn undo(&mut self, n: usize) -> Result<Vec<Op>> {
+        let journal = self.journal()?;
+        let mut reverted = vec![];
+        for entry in journal.to_undo(n) {
+            for op in journal.ops(entry.seq).iter().rev() {
+                let inverse = op.inverse();
+                if self.apply(&inverse) {
+                    reverted.push(inverse);
+                } else {
+                    log::error!("cannot undo '{op}', the task was changed externally");
+                }
+            }
+            self.replayed.push(Action::Undo { target: entry.seq });
+        }
+        Ok(reverted)
+    }
+
+    /// Applies again up to `n` latest undone changes. Returns the applied
+    /// operations.
+    pub fn redo(&mut self, n: usize) -> Result<Vec<Op>> {
+        let journal = self.journal()?;
+        let mut applied = vec![];
+        for entry in journal.to_redo(n) {
+            for op in journal.ops(entry.seq) {
+                if self.apply(op) {
+                    applied.push(op.clone());
+                } else {
+                    log::error!("cannot redo '{op}', the task was changed externally");
+                }
+            }
+            self.replayed.push(Action::Redo { target: entry.seq });
+        }
+        Ok(applied)
+    }
+
+    fn next_loc(&self) -> Loc {
+        // We might have assumed the last vec element is the latest hence has the
+        // greatest ID, but the tasks file may be externally shuffled so seq scan
+        // is the only option. Rejected tasks keep their IDs to be restored by
+        // hand, whether they are still in the file or already moved aside.
+        let next_id = self
+            .inner
+            .iter()
+            .map(|t| t.id)
+            .chain(self.report.rejected.iter().filter_map(|r| r.id))
+            .chain([self.quarantined_max])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let next_idx = self.inner.len();
+        Loc::new(next_idx, next_id)
+    }
+
+    /// Adds a task made with [`Task::new`] under a new ID
+    pub fn add(&mut self, mut task: Task) -> Loc {
+        let loc = self.next_loc();
+        debug_assert_eq!(loc.idx, self.inner.len().into());
+        task.id = loc.id;
+        self.inner.push(task.clone());
+        self.record(Op::Add { task });
+        self.added.insert(loc.id);
+        loc
+    }
+
+    /// Applies the tasks touched since loading on top of `theirs`, the tasks
+    /// currently stored in the file. Every other task is taken from `theirs`.
+    /// Edits of tasks removed in `theirs` are dropped, not journaled.
+    fn merge(&mut self, mut theirs: Vec<Task>) -> Vec<Task> {
+        let mut next_id = theirs
+            .iter()
+            .chain(self.iter())
+            .map(|t| t.id)
+            .chain([self.quarantined_max])
+            .max()
+            .unwrap_or(0);
+        let mut added: Vec<_> = self.added.iter().copied().collect();
+        added.sort_unstable();
+        for id in added {
+            if theirs.iter().any(|t| t.id == id) {
+                next_id += 1;
+                self.renumber(id, next_id);
+            }
+        }
+        let mut lost = HashSet::new();
+        for task in self.iter().filter(|t| self.touched.contains(&t.id)) {
+            match theirs.iter_mut().find(|t| t.id == task.id) {
+                Some(their) => *their = task.clone(),
+                None if self.added.contains(&task.id) => theirs.push(task.clone()),
+                None => {
+                    log::warn!(
+                        "task {} '{}' was removed by someone else, dropping its changes",
+                        task.id,
+                        task.title
+                    );
+                    lost.insert(task.id);
+                }
+            }
+        }
+        self.ops.retain(|op| !lost.contains(&op.id()));
+        let ours: HashSet<_> = self.iter().map(|t| t.id).collect();
+        theirs.retain(|t| !self.touched.contains(&t.id) || ours.contains(&t.id));
+        theirs
+    }
+
+    /// Gives a new ID to a task created since loading
+    fn renumber(&mut self, from: usize, to: usize) {
+        for task in self.inner.iter_mut().filter(|t| t.id == from) {
+            log::warn!(
+                "task ID {from} was taken concurrently, '{}' got ID {to}",
+                task.title
+            );
+            task.id = to;
+        }
+        for task in &mut self.inner {
+            if task.parent == Some(from) {
+                task.parent = Some(to);
+            }
+            for id in task.blocked_by.iter_mut().filter(|id| **id == from) {
+                *id = to;
+            }
+        }
+        for op in &mut self.ops {
+            op.renumber(from, to);
+        }
+        if let Some(Some(timer)) = &mut self.timer
+            && timer.id == from
+        {
+            timer.id = to;
+        }
+        for ids in [&mut self.touched, &mut self.added] {
+            if ids.remove(&from) {
+                ids.insert(to);
+            }
+        }
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        if !self.report.rejected.is_empty() {
+            return Err(Self::rejected_error(&self.filename, &self.report));
+        }
+        if self.lock.is_none() {
+            self.lock = Some(FileLock::acquire(
+                &lock::lock_path(&self.filename),
+                LockMode::Exclusive,
+            )?);
+        }
+        if let Ok(current) = self.storage.fingerprint()
+            && current != self.fingerprint
+        {
+            log::warn!(
+                "{:?} was modified by someone else, merging {} changed tasks",
+                self.filename,
+                self.touched.len()
+            );
+            let theirs = self.storage.load()?.tasks;
+            self.inner = self.merge(theirs);
+        }
+        self.fingerprint = self.storage.save(&self.inner, &self.touched)?;
+        self.touched.clear();
+        self.added.clear();
+        log::info!("file saved");
+        if let Some(timer) = self.timer.take() {
+            Timer::store(timer.as_ref(), &timer::timer_path(&self.filename))?;
+        }
+        self.write_journal()
+    }
+
+    fn write_journal(&mut self) -> Result<()> {
+        if self.replayed.is_empty() && self.ops.is_empty() {
+            return Ok(());
+        }
+        let mut journal = self.journal()?;
+        for action in self.replayed.drain(..) {
+            journal.append(action)?;
+        }
+        if !self.ops.is_empty() {
+            let ops = std::mem::take(&mut self.ops);
+            journal.append(Action::Do { ops })?;
+        }
+        Ok(())
+    }
+
+    pub fn find_idx(&self, idx: Idx) -> Option<&Task> {
+        let i: usize = idx.into();
+        self.inner.get(i)
+    }
+
+    fn find_idx_mut(&mut self, idx: Idx) -> Option<&mut Task> {
+        let i: usize = idx.into();
+        self.inner.get_mut(i)
+    }
+
+    pub fn find(&self, needle: &str, show_dropped: bool, empty_show_all: bool) -> Vec<Found<'_>> {
+        let needle = needle.trim().to_lowercase();
+        let mut candidates = vec![];
+        if needle.is_empty() {
+            return match empty_show_all {
+                true => self
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, task)| Found::new(idx, task, None))
+                    .collect(),
+                false => candidates,
+            };
+        }
+        log::debug!("searching candidates for '{needle}'");
+        for (idx, task) in self.iter().enumerate() {
+            let candidate = Candidate::check(&needle, task)
+                .or_else(|| Candidate::check(&translate(&needle), task));
+            log::debug!("candidate '{task}' result is {candidate:?}");
+            if let Some(candidate) = candidate {
+                match candidate {
+                    Candidate::ById if show_dropped || task.status.is_visible() => {
+                        log::debug!("searching stopped because ID was found");
+                        return vec![Found::new(idx, task, Some(candidate))];
+                    }
+                    _ => candidates.push(Found::new(idx, task, Some(candidate))),
+                }
+            }
+        }
+        log::debug!("searching complete");
+
+        if !show_dropped {
+            candidates.retain(|found| found.task.status.is_visible());
+        }
+        candidates
+    }
+
+    /// Tasks with IDs in the given ranges, see [`filter_parser::id_list`]
+    pub fn find_ids(&self, ids: &[RangeInclusive<usize>], show_dropped: bool) -> Vec<Found<'_>> {
+        self.iter()
+            .enumerate()
+            .filter(|(_, task)| ids.iter().any(|range| range.contains(&task.id)))
+            .filter(|(_, task)| show_dropped || task.status.is_visible())
+            .map(|(idx, task)| Found::new(idx, task, Some(Candidate::ById)))
+            .collect()
+    }
+
+    /// Like [`Tasks::find`] including dropped tasks, but the query may start
+    /// with a date filter, e.g. `created after 2025-05-04 milk`
+    pub fn query(&self, query: &str) -> Vec<Found<'_>> {
+        let query = query.to_lowercase();
+        let (filters, needle) = filter_parser::filters(&query);
+        log::info!("filters are {filters:?}");
+        self.find(needle, true, !filters.is_empty())
+            .into_iter()
+            .filter(|found| filters.matches(found.task))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Task> {
+        self.inner.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Change;
+    use crate::filter_parser::TimeUnit;
+    use crate::storage::tests::round_trip;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exclusive_load_blocks_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        let lock_file = lock::lock_path(&path);
+        let err = FileLock::acquire_timeout(&lock_file, LockMode::Shared, Duration::ZERO);
+        assert!(err.is_err());
+        drop(tasks);
+        assert!(FileLock::acquire_timeout(&lock_file, LockMode::Shared, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_history_survives_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.add(Task::new("buy beer".into(), Status::Todo));
+        tasks.set_done_idx(Idx(0));
+        tasks.set_done_idx(Idx(0));
+        tasks.change_title_idx(Idx(0), "buy oat milk".into());
+        tasks.comment_idx(Idx(0), CommentEdit::Add("lactose free".into()));
+        tasks.save().unwrap();
+        drop(tasks);
+
+        let tasks = Tasks::load(path, LockMode::Shared).unwrap();
+        let changes: Vec<_> = tasks
+            .find_idx(Idx(0))
+            .unwrap()
+            .history
+            .iter()
+            .map(|e| e.change.clone())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                Change::Status {
+                    from: Status::Todo,
+                    to: Status::Done
+                },
+                Change::Renamed {
+                    from: "buy milk".into(),
+                    to: "buy oat milk".into()
+                },
+                Change::Commented,
+            ]
+        );
+        assert!(tasks.find_idx(Idx(1)).unwrap().history.is_empty());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.add(Task::new("buy beer".into(), Status::Todo));
+        tasks.save().unwrap();
+        tasks.set_done_idx(Idx(0));
+        tasks.save().unwrap();
+        // Already done, nothing to journal and to undo
+        tasks.set_status_idx(Idx(0), Status::Done);
+        tasks.save().unwrap();
+        tasks.drop_done();
+        tasks.remove_dropped();
+        tasks.save().unwrap();
+        assert_eq!(tasks.iter().count(), 1);
+
+        assert_eq!(tasks.undo(1).unwrap().len(), 2);
+        tasks.save().unwrap();
+        assert_eq!(tasks.find_idx(Idx(1)).unwrap().status, Status::Done);
+
+        assert_eq!(tasks.undo(5).unwrap().len(), 3);
+        tasks.save().unwrap();
+        assert_eq!(tasks.iter().count(), 0);
+
+        tasks.redo(2).unwrap();
+        tasks.save().unwrap();
+        let statuses: Vec<_> = tasks.iter().map(|t| t.status).collect();
+        assert_eq!(statuses, [Status::Done, Status::Todo]);
+        assert!(tasks.undo(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_done_spawns_next_occurrence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path, LockMode::Exclusive).unwrap();
+        let today = Local::now().date_naive();
+        let mut report = Task::new("weekly report".into(), Status::Todo);
+        report.recurrence = Some(Recurrence::Every(1, TimeUnit::Weeks));
+        report.due = Some(today);
+        let loc = tasks.add(report);
+        tasks.save().unwrap();
+
+        tasks.set_done_idx(loc.idx);
+        // Already done tasks don't repeat twice
+        tasks.set_done_idx(loc.idx);
+        let spawned = tasks.take_spawned();
+        assert_eq!(spawned.len(), 1);
+        let next = tasks.find_idx(spawned[0].idx).unwrap();
+        assert_eq!((next.id, next.status), (2, Status::Todo));
+        assert_eq!(next.due, Some(today + chrono::TimeDelta::weeks(1)));
+        assert_eq!(next.recurrence, Some(Recurrence::Every(1, TimeUnit::Weeks)));
+        tasks.save().unwrap();
+
+        // Undo reverts both the status and the new occurrence
+        tasks.undo(1).unwrap();
+        tasks.save().unwrap();
+        let statuses: Vec<_> = tasks.iter().map(|t| t.status).collect();
+        assert_eq!(statuses, [Status::Todo]);
+
+        // Neither reopening nor undoing spawns a second occurrence
+        tasks.set_done_idx(loc.idx);
+        tasks.set_todo_idx(loc.idx);
+        tasks.set_done_idx(loc.idx);
+        assert_eq!(tasks.take_spawned().len(), 1);
+        tasks.save().unwrap();
+        tasks.undo(1).unwrap();
+        tasks.set_done_idx(loc.idx);
+        tasks.set_todo_idx(loc.idx);
+        tasks.set_done_idx(loc.idx);
+        assert_eq!(tasks.take_spawned().len(), 1);
+        let open = tasks.iter().filter(|t| t.status == Status::Todo).count();
+        assert_eq!(open, 1);
+    }
+
+    #[test]
+    fn test_subtasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tasks = Tasks::load(dir.path().join("tasks.csv"), LockMode::Exclusive).unwrap();
+        let parent = tasks.add(Task::new("migrate billing".into(), Status::Todo));
+        let subtask = |title: &str, parent: usize| Task {
+            parent: Some(parent),
+            ..Task::new(title.into(), Status::Todo)
+        };
+        let export = tasks.add(subtask("export invoices", parent.id));
+        tasks.add(subtask("check totals", export.id));
+        tasks.add(subtask("switch provider", parent.id));
+        tasks.set_done_idx(export.idx);
+
+        let ids = |found: Vec<Found>| found.iter().map(|f| f.task.id).collect::<Vec<_>>();
+        assert_eq!(ids(tasks.subtasks(parent.id)), [2, 4, 3]);
+        assert_eq!(tasks.progress(parent.id), Some((1, 2)));
+        assert_eq!(tasks.progress(4), None);
+        let stored: Vec<_> = tasks.iter().cloned().collect();
+        for (format, loaded) in round_trip(&stored) {
+            let parents: Vec<_> = loaded.iter().map(|t| t.parent).collect();
+            assert_eq!(parents, [None, Some(1), Some(2), Some(1)], "{format:?}");
+        }
+
+        tasks.remove(parent.idx);
+        assert!(tasks.iter().all(|task| task.parent != Some(parent.id)));
+        assert_eq!(tasks.progress(export.id), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_blocked_by() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tasks = Tasks::load(dir.path().join("tasks.csv"), LockMode::Exclusive).unwrap();
+        let design = tasks.add(Task::new("design".into(), Status::Todo));
+        let build = tasks.add(Task::new("build".into(), Status::Todo));
+        let ship = tasks.add(Task::new("ship".into(), Status::Todo));
+        tasks.block_idx(build.idx, design.id).unwrap();
+        tasks.block_idx(ship.idx, build.id).unwrap();
+        tasks.block_idx(ship.idx, design.id).unwrap();
+        assert_eq!(tasks.find_idx(ship.idx).unwrap().blocked_by, [2, 1]);
+        let stored: Vec<_> = tasks.iter().cloned().collect();
+        for (format, loaded) in round_trip(&stored) {
+            let blocked_by: Vec<_> = loaded.iter().map(|t| t.blocked_by.clone()).collect();
+            assert_eq!(blocked_by, [vec![], vec![1], vec![2, 1]], "{format:?}");
+        }
+
+        let err = tasks.block_idx(design.idx, ship.id).unwrap_err();
+        assert!(matches!(err, Error::Cycle(ids) if ids == [1, 3, 1]));
+        assert!(matches!(
+            tasks.block_idx(design.idx, design.id),
+            Err(Error::Cycle(_))
+        ));
+
+        tasks.set_done_idx(design.idx);
+        let unblocked: Vec<_> = tasks.take_unblocked().iter().map(|loc| loc.id).collect();
+        assert_eq!(unblocked, [build.id]);
+        assert!(tasks.is_blocked(tasks.find_idx(ship.idx).unwrap()));
+        tasks.set_done_idx(build.idx);
+        assert_eq!(tasks.take_unblocked().len(), 1);
+        let ship_task = tasks.find_idx(ship.idx).unwrap();
+        assert_eq!(ship_task.blocked_by, [2, 1]);
+        assert!(!tasks.is_blocked(ship_task));
+
+        // Reopening a blocker blocks its dependents again
+        tasks.set_todo_idx(design.idx);
+        assert!(tasks.is_blocked(tasks.find_idx(build.idx).unwrap()));
+        assert!(tasks.is_blocked(tasks.find_idx(ship.idx).unwrap()));
+        tasks.set_done_idx(design.idx);
+        let unblocked: Vec<_> = tasks.take_unblocked().iter().map(|loc| loc.id).collect();
+        assert_eq!(unblocked, [ship.id]);
+    }
+
+    #[test]
+    fn test_timer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        let report = tasks.add(Task::new("quarterly report".into(), Status::Todo));
+        assert!(matches!(tasks.stop().unwrap(), Stopped::NotRunning));
+        tasks.start_idx(report.idx);
+        tasks.save().unwrap();
+        drop(tasks);
+
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        assert_eq!(tasks.timer().unwrap().map(|t| t.id), Some(report.id));
+        let Stopped::Tracked(loc, entry) = tasks.stop().unwrap() else {
+            panic!("the timer is not stopped");
+        };
+        assert_eq!(loc.id, report.id);
+        tasks.add_time_idx(
+            report.idx,
+            TimeEntry {
+                seconds: 5400,
+                ..entry
+            },
+        );
+        tasks.save().unwrap();
+        drop(tasks);
+
+        let mut tasks = Tasks::load(path, LockMode::Exclusive).unwrap();
+        assert_eq!(tasks.timer().unwrap(), None);
+        let task = tasks.find_idx(report.idx).unwrap();
+        assert_eq!(task.time.len(), 2);
+        assert_eq!(task.spent(), entry.seconds + 5400);
+        for (format, loaded) in round_trip(std::slice::from_ref(task)) {
+            assert_eq!(loaded[0].time, task.time, "{format:?}");
+        }
+        tasks.undo(1).unwrap();
+        assert!(tasks.find_idx(report.idx).unwrap().time.is_empty());
+
+        // The time of a removed task is reported, not silently dropped
+        tasks.start_idx(report.idx);
+        tasks.remove(report.idx);
+        assert!(matches!(
+            tasks.stop().unwrap(),
+            Stopped::Lost { id, .. } if id == report.id
+        ));
+        assert_eq!(tasks.timer().unwrap(), None);
+    }
+
+    #[test]
+    fn test_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path, LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        let login = tasks.add(Task::new("fix login".into(), Status::Todo));
+        tasks.add(Task::new("deploy".into(), Status::Done));
+        tasks.save().unwrap();
+
+        tasks.set_project_idx(login.idx, Some("work".into()));
+        tasks.set_project_idx(Idx(2), Some("work".into()));
+        tasks.save().unwrap();
+        assert_eq!(tasks.project_counts(), [(None, 1), (Some("work"), 1)]);
+        let stored: Vec<_> = tasks.iter().cloned().collect();
+        for (format, loaded) in round_trip(&stored) {
+            let projects: Vec<_> = loaded.iter().map(|t| t.project.as_deref()).collect();
+            assert_eq!(projects, [None, Some("work"), Some("work")], "{format:?}");
+        }
+
+        tasks.undo(1).unwrap();
+        assert_eq!(tasks.project_counts(), [(None, 2)]);
+    }
+
+    #[test]
+    fn test_locate() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().join("home");
+        let repo = home.join("repo");
+        let deep = repo.join("src/deep");
+        fs::create_dir_all(&deep).unwrap();
+
+        assert_eq!(project_dir(&deep, &home, TASKS_CSV), None);
+        let (path, source) = locate(None, None, None, &home);
+        assert_eq!(path, home.join(".todo/tasks.csv"));
+        assert_eq!(source, PathSource::Home);
+
+        assert_eq!(Tasks::init(&repo).unwrap(), repo.join(".todo/tasks.csv"));
+        assert!(matches!(Tasks::init(&repo), Err(Error::AlreadyExists(_))));
+        let project = project_dir(&deep, &home, TASKS_CSV);
+        assert_eq!(project, Some(repo.as_path()));
+        // A nested `.todo` with only a config doesn't hide the tasks above it
+        let nested = repo.join("src/.todo");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join(config::CONFIG_TOML), "confirm = false\n").unwrap();
+        assert_eq!(project_dir(&deep, &home, TASKS_CSV), project);
+        assert_eq!(
+            project_dir(&deep, &home, config::CONFIG_TOML),
+            Some(repo.join("src").as_path())
+        );
+        let (path, source) = locate(None, None, project, &home);
+        assert_eq!(path, repo.join(".todo/tasks.csv"));
+        assert_eq!(source, PathSource::Local(repo.clone()));
+
+        // The global config loses to the project directory, the local one wins
+        let mut configured = Configured {
+            path: home.join("tasks.jsonl"),
+            config: home.join(".config/todo/config.toml"),
+            local: false,
+        };
+        let (path, _) = locate(None, Some(&configured), project, &home);
+        assert_eq!(path, repo.join(".todo/tasks.csv"));
+        configured.local = true;
+        let (path, _) = locate(None, Some(&configured), project, &home);
+        assert_eq!(path, home.join("tasks.jsonl"));
+
+        let (path, source) = locate(Some("other.csv".into()), None, project, &home);
+        assert_eq!((path, source), ("other.csv".into(), PathSource::Env));
+    }
+
+    #[test]
+    fn test_save_merges_external_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.add(Task::new("buy beer".into(), Status::Todo));
+        tasks.save().unwrap();
+
+        tasks.unlock();
+        tasks.change_title_idx(Idx(0), "buy oat milk".into());
+        tasks.add(Task::new("bake bread".into(), Status::Todo));
+
+        let mut other = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        other.set_done_idx(Idx(1));
+        other.add(Task::new("learn to make beer".into(), Status::Todo));
+        other.save().unwrap();
+        drop(other);
+
+        tasks.save().unwrap();
+        let mut saved: Vec<_> = tasks
+            .iter()
+            .map(|t| (t.id, t.title.as_str(), t.status))
+            .collect();
+        saved.sort_by_key(|(id, _, _)| *id);
+        assert_eq!(
+            saved,
+            [
+                (1, "buy oat milk", Status::Todo),
+                (2, "buy beer", Status::Done),
+                (3, "learn to make beer", Status::Todo),
+                (4, "bake bread", Status::Todo),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_drops_edits_of_removed_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.add(Task::new("buy beer".into(), Status::Todo));
+        tasks.save().unwrap();
+
+        tasks.unlock();
+        tasks.change_title_idx(Idx(0), "buy oat milk".into());
+        tasks.set_done_idx(Idx(1));
+
+        let mut other = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        other.remove(Idx(0));
+        other.save().unwrap();
+        drop(other);
+
+        tasks.save().unwrap();
+        let saved: Vec<_> = tasks.iter().map(|t| (t.id, t.status)).collect();
+        assert_eq!(saved, [(2, Status::Done)]);
+        // Only the kept change can be undone
+        tasks.undo(1).unwrap();
+        let saved: Vec<_> = tasks.iter().map(|t| (t.id, t.status)).collect();
+        assert_eq!(saved, [(2, Status::Todo)]);
+    }
+
+    #[test]
+    fn test_save_after_file_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        tasks.add(Task::new("buy milk".into(), Status::Todo));
+        tasks.save().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        tasks.add(Task::new("buy beer".into(), Status::Todo));
+        tasks.save().unwrap();
+        drop(tasks);
+        let saved = Tasks::load(path, LockMode::Shared).unwrap();
+        assert_eq!(saved.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_doctor_fix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        std::fs::write(
+            &path,
+            "id,status,title,created_at,updated_at,comments\n\
+             1,todo,buy milk,2025-05-04T10:00:00Z,2025-05-04T09:00:00Z,\n\
+             1,todo,buy beer,2025-05-04T10:00:00Z,2025-05-04T10:00:00Z,\n\
+             2,bogus,bake,2025-05-04T10:00:00Z,2025-05-04T10:00:00Z,\n",
+        )
+        .unwrap();
+        assert!(Tasks::load(path.clone(), LockMode::Exclusive).is_err());
+
+        let mut tasks = Tasks::load_lenient(path.clone(), LockMode::Exclusive).unwrap();
+        let issues = tasks.diagnose();
+        assert_eq!(issues.len(), 3);
+        tasks.repair(&issues).unwrap();
+        tasks.save().unwrap();
+        drop(tasks);
+
+        let tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        assert!(tasks.diagnose().is_empty());
+        let ids: Vec<_> = tasks.iter().map(|t| (t.id, t.title.as_str())).collect();
+        // ID 2 stays with the rejected task
+        assert_eq!(ids, [(1, "buy milk"), (3, "buy beer")]);
+        let rejected = std::fs::read_to_string(storage::rejected_path(&path)).unwrap();
+        assert!(rejected.contains("2,bogus,bake"));
+    }
+
+    #[test]
+    fn test_add_after_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        std::fs::write(
+            &path,
+            "id,status,title\n1,todo,buy milk\n2,bogus,bake\n3,bogus,brew\n",
+        )
+        .unwrap();
+        let mut tasks = Tasks::load_lenient(path.clone(), LockMode::Exclusive).unwrap();
+        let issues = tasks.diagnose();
+        tasks.repair(&issues).unwrap();
+        assert_eq!(tasks.add(Task::new("buy beer".into(), Status::Todo)).id, 4);
+        tasks.save().unwrap();
+        drop(tasks);
+
+        // The quarantined IDs stay taken after reloading
+        let mut tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        assert_eq!(
+            tasks.add(Task::new("bake bread".into(), Status::Todo)).id,
+            5
+        );
+    }
+
+    #[test]
+    fn test_doctor_fix_all_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.csv");
+        std::fs::write(&path, "id,status,title\n1,bogus,bake\n").unwrap();
+        assert!(Tasks::load(path.clone(), LockMode::Exclusive).is_err());
+
+        let mut tasks = Tasks::load_lenient(path.clone(), LockMode::Exclusive).unwrap();
+        let issues = tasks.diagnose();
+        assert_eq!(issues.len(), 1);
+        tasks.repair(&issues).unwrap();
+        assert_eq!(
+            tasks.add(Task::new("bake bread".into(), Status::Todo)).id,
+            2
+        );
+        tasks.save().unwrap();
+        drop(tasks);
+
+        let tasks = Tasks::load(path.clone(), LockMode::Exclusive).unwrap();
+        assert_eq!(tasks.iter().count(), 1);
+        assert!(storage::rejected_path(&path).exists());
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The running timer of `todo start`, a single one per tasks file. Lives next
+/// to the tasks file with `.timer` suffix as a JSON object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timer {
+    /// ID of the task the time goes to
+    pub id: usize,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Timer {
+    pub fn start(id: usize) -> Self {
+        Self {
+            id,
+            started_at: Utc::now(),
+        }
+    }
+
+    /// Seconds passed since the start
+    pub fn elapsed(&self, now: DateTime<Utc>) -> u64 {
+        (now - self.started_at).num_seconds().max(0) as u64
+    }
+
+    /// `None` if no timer is running
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the running timer, `None` removes the file
+    pub fn store(timer: Option<&Self>, path: &Path) -> io::Result<()> {
+        match timer {
+            Some(timer) => fs::write(path, serde_json::to_string(timer)?),
+            None => match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// The timer lives next to the tasks file with `.timer` suffix
+pub fn timer_path(filename: &Path) -> PathBuf {
+    let mut name = filename.as_os_str().to_owned();
+    name.push(".timer");
+    name.into()
+}